@@ -0,0 +1,321 @@
+//! Capture files of session traffic
+//!
+//! A capture file records every chunk of bytes received from and sent to the serial port with
+//! the direction and the time, so that the session can be replayed later.
+//!
+//! The file starts with the magic `SCCAP`, the format version `1`, and the header: a 32-bit
+//! little-endian length followed by UTF-8 text of `name=value` lines. The first line is
+//! `start=<time in RFC 3339>`.
+//! Each record follows as:
+//!
+//! * 64-bit little-endian microseconds since the start
+//! * direction byte: `0` for received or `1` for sent
+//! * 32-bit little-endian length of the bytes
+//! * the bytes
+
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8] = b"SCCAP\x01";
+
+/// Direction of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the serial port.
+    Received,
+    /// Sent to the serial port.
+    Sent,
+}
+
+/// Description of a captured session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Wall clock time when the capture started.
+    pub start: DateTime<FixedOffset>,
+    /// Settings of the session, such as the port and the line settings, by `(name, value)`.
+    pub settings: Vec<(String, String)>,
+}
+
+impl Header {
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "start={}\n",
+            self.start.to_rfc3339_opts(SecondsFormat::Micros, false)
+        );
+        for (name, value) in &self.settings {
+            text.push_str(&format!("{}={}\n", name, value));
+        }
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Header> {
+        let mut lines = text.lines().map(|line| {
+            let mut split = line.splitn(2, '=');
+            Some((split.next()?, split.next()?))
+        });
+        let start = match lines.next()?? {
+            ("start", start) => DateTime::parse_from_rfc3339(start).ok()?,
+            _ => return None,
+        };
+        let settings = lines
+            .map(|line| line.map(|(name, value)| (name.to_owned(), value.to_owned())))
+            .collect::<Option<_>>()?;
+        Some(Header { start, settings })
+    }
+}
+
+/// Chunk of bytes in a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the start.
+    pub time: Duration,
+    /// Whether the bytes were received or sent.
+    pub direction: Direction,
+    /// Bytes of the chunk.
+    pub bytes: Vec<u8>,
+}
+
+/// Writer of a capture file.
+///
+/// ```
+///     # use serialcat::capture::{CaptureReader, CaptureWriter, Direction, Header};
+///     # use std::time::Instant;
+///
+///     let header = Header {
+///         start: chrono::Local::now().into(),
+///         settings: vec![("port".to_owned(), "/dev/ttyACM0".to_owned())],
+///     };
+///     let start = Instant::now();
+///     let mut capture = CaptureWriter::new(Vec::new(), &header, start).unwrap();
+///     capture.write(Direction::Sent, b"ping\r", start).unwrap();
+///
+///     let file = capture.into_inner();
+///     let mut capture = CaptureReader::new(&file[..]).unwrap();
+///     assert_eq!(capture.header().settings, header.settings);
+///     assert_eq!(capture.read_record().unwrap().unwrap().bytes, b"ping\r");
+///     assert_eq!(capture.read_record().unwrap(), None);
+/// ```
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    writer: W,
+    start: Instant,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Start a capture at `start` by writing the header.
+    pub fn new(mut writer: W, header: &Header, start: Instant) -> io::Result<CaptureWriter<W>> {
+        let text = header.to_text();
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(text.as_bytes());
+        writer.write_all(&buffer)?;
+        writer.flush()?;
+        Ok(CaptureWriter {
+            writer,
+            start,
+            buffer,
+        })
+    }
+
+    /// Write the bytes at `time` as a record, and flush it.
+    pub fn write(&mut self, direction: Direction, bytes: &[u8], time: Instant) -> io::Result<()> {
+        let micros = time.saturating_duration_since(self.start).as_micros() as u64;
+        let direction = match direction {
+            Direction::Received => 0,
+            Direction::Sent => 1,
+        };
+
+        // Write a record at once not to leave a half of it
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&micros.to_le_bytes());
+        self.buffer.push(direction);
+        self.buffer
+            .extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        self.buffer.extend_from_slice(bytes);
+        self.writer.write_all(&self.buffer)?;
+        self.writer.flush()
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reader of a capture file.
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+    header: Header,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Start reading a capture by reading the header.
+    pub fn new(mut reader: R) -> io::Result<CaptureReader<R>> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        if magic[..] != *MAGIC {
+            return Err(invalid("not a capture file"));
+        }
+
+        // Not to allocate the length read from a corrupt file at once
+        let len = read_u32(&mut reader)?;
+        let mut text = Vec::new();
+        (&mut reader).take(len.into()).read_to_end(&mut text)?;
+        if text.len() != len as usize {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let header = String::from_utf8(text)
+            .ok()
+            .and_then(|text| Header::from_text(&text))
+            .ok_or_else(|| invalid("invalid header"))?;
+
+        Ok(CaptureReader { reader, header })
+    }
+
+    /// Header of the capture.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Read the next record, or `None` at the end of the file.
+    pub fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut micros = [0; 8];
+        match self.reader.read_exact(&mut micros[..1]) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        self.reader
+            .read_exact(&mut micros[1..])
+            .map_err(truncated)?;
+        let time = Duration::from_micros(u64::from_le_bytes(micros));
+
+        let mut direction = [0];
+        self.reader.read_exact(&mut direction).map_err(truncated)?;
+        let direction = match direction[0] {
+            0 => Direction::Received,
+            1 => Direction::Sent,
+            _ => return Err(invalid("invalid direction")),
+        };
+
+        let len = read_u32(&mut self.reader)?;
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(len.into())
+            .read_to_end(&mut bytes)?;
+        if bytes.len() != len as usize {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        Ok(Some(Record {
+            time,
+            direction,
+            bytes,
+        }))
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        invalid("truncated capture file")
+    } else {
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            start: DateTime::parse_from_rfc3339("2020-01-02T03:04:05.678901+09:00").unwrap(),
+            settings: vec![
+                ("port".to_owned(), "/dev/ttyACM0".to_owned()),
+                ("line".to_owned(), "115200 8N1".to_owned()),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut capture = CaptureWriter::new(Vec::new(), &header(), start).unwrap();
+        capture.write(Direction::Sent, b"ls\r", at(10)).unwrap();
+        capture
+            .write(Direction::Received, b"ls\r\n", at(12))
+            .unwrap();
+        capture.write(Direction::Received, b"", at(1500)).unwrap();
+        let file = capture.into_inner();
+        assert!(file.starts_with(
+            b"SCCAP\x01\x49\0\0\0start=2020-01-02T03:04:05.678901+09:00\nport=/dev/ttyACM0\n"
+        ));
+
+        let mut capture = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(capture.header(), &header());
+        for (millis, direction, bytes) in &[
+            (10, Direction::Sent, &b"ls\r"[..]),
+            (12, Direction::Received, b"ls\r\n"),
+            (1500, Direction::Received, b""),
+        ] {
+            assert_eq!(
+                capture.read_record().unwrap(),
+                Some(Record {
+                    time: Duration::from_millis(*millis),
+                    direction: *direction,
+                    bytes: bytes.to_vec(),
+                })
+            );
+        }
+        assert_eq!(capture.read_record().unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_file() {
+        let start = Instant::now();
+        let mut capture = CaptureWriter::new(Vec::new(), &header(), start).unwrap();
+        capture.write(Direction::Received, b"abc", start).unwrap();
+        let file = capture.into_inner();
+
+        let error = |file: &[u8]| CaptureReader::new(file).unwrap_err().to_string();
+        assert_eq!(error(b"SCCAP\x02"), "not a capture file");
+        assert_eq!(error(b"SCC"), "truncated capture file");
+        assert_eq!(error(&file[..12]), "truncated capture file");
+        assert_eq!(error(b"SCCAP\x01\x03\0\0\0a=b"), "invalid header");
+        assert_eq!(
+            error(b"SCCAP\x01\xff\xff\xff\xffa=b"),
+            "truncated capture file"
+        );
+
+        let mut capture = CaptureReader::new(&file[..file.len() - 1]).unwrap();
+        assert_eq!(
+            capture.read_record().unwrap_err().to_string(),
+            "truncated capture file"
+        );
+
+        let mut file = file;
+        let len = file.len();
+        file[len - 8] = 2;
+        let mut capture = CaptureReader::new(&file[..]).unwrap();
+        assert_eq!(
+            capture.read_record().unwrap_err().to_string(),
+            "invalid direction"
+        );
+    }
+}
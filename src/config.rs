@@ -0,0 +1,288 @@
+//! Configuration files with named profiles
+//!
+//! A profile sets options by their long names, with `-` or `_`:
+//!
+//! ```toml
+//! [profile.nucleo]
+//! port = "usb:0483:374b"
+//! baud-rate = 921600
+//! flow-control = "hardware"
+//! reconnect = true
+//! exit-on = ["PASS", "3:^ERROR"]
+//! ```
+//!
+//! Profiles are merged option by option from [paths](fn.paths.html) in order, so that
+//! `./.serialcat.toml` overrides `$XDG_CONFIG_HOME/serialcat/config.toml`.
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::Value;
+
+/// Configuration file in the current directory, for the project.
+pub const PROJECT_FILE: &str = ".serialcat.toml";
+
+/// Options set by a profile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Serial port, used when not given on the command line.
+    pub port: Option<String>,
+    /// Arguments of each long option, such as `--baud-rate=921600`.
+    ///
+    /// Empty for a flag set to `false`, to override the flag in a file of lower priority.
+    pub options: BTreeMap<String, Vec<String>>,
+}
+
+impl Profile {
+    /// Command line arguments to set the options.
+    pub fn args(&self) -> Vec<OsString> {
+        self.options
+            .values()
+            .flatten()
+            .map(OsString::from)
+            .collect()
+    }
+
+    fn parse(table: &toml::value::Table) -> Result<Profile> {
+        let mut profile = Profile::default();
+        for (key, value) in table {
+            let long = key.replace('_', "-");
+            let args = match (long.as_str(), value) {
+                ("port", Value::String(port)) => {
+                    profile.port = Some(port.clone());
+                    continue;
+                }
+                ("port", _) => bail!("Serial port must be a string"),
+                ("profile", _) => bail!("Profile cannot select a profile"),
+                (_, Value::Boolean(true)) => vec![format!("--{}", long)],
+                (_, Value::Boolean(false)) => Vec::new(),
+                (_, Value::Array(values)) => values
+                    .iter()
+                    .map(|value| option_arg(&long, value))
+                    .collect::<Result<_>>()?,
+                (_, value) => vec![option_arg(&long, value)?],
+            };
+            profile.options.insert(long, args);
+        }
+        Ok(profile)
+    }
+
+    /// Override options by the other profile.
+    fn merge(&mut self, other: Profile) {
+        if other.port.is_some() {
+            self.port = other.port;
+        }
+        self.options.extend(other.options);
+    }
+}
+
+fn option_arg(long: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(format!("--{}={}", long, s)),
+        Value::Integer(n) => Ok(format!("--{}={}", long, n)),
+        _ => bail!(
+            "Value of {} must be a string, an integer or a boolean",
+            long
+        ),
+    }
+}
+
+/// Profiles from configuration files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Parse a configuration file.
+    pub fn parse(text: &str) -> Result<Config> {
+        let mut config = Config::default();
+        let table = match text.parse::<Value>()? {
+            Value::Table(table) => table,
+            _ => unreachable!("TOML document is a table"),
+        };
+        for (key, value) in table {
+            let profiles = match (key.as_str(), value) {
+                ("profile", Value::Table(profiles)) => profiles,
+                ("profile", _) => bail!("profile must be tables of profiles"),
+                _ => bail!("Unknown key: {}", key),
+            };
+            for (name, profile) in profiles {
+                let profile = match profile {
+                    Value::Table(profile) => Profile::parse(&profile),
+                    _ => Err(anyhow!("Profile must be a table")),
+                }
+                .with_context(|| format!("Invalid profile: {}", name))?;
+                config.profiles.insert(name, profile);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read and merge the configuration files which exist, from the lowest priority.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Config> {
+        let mut config = Config::default();
+        for path in paths {
+            let path = path.as_ref();
+            if !path.exists() {
+                continue;
+            }
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Cannot read configuration file: {}", path.display()))?;
+            let file = Config::parse(&text)
+                .with_context(|| format!("Invalid configuration file: {}", path.display()))?;
+            config.merge(file);
+        }
+        Ok(config)
+    }
+
+    /// Override profiles by the other configuration, option by option.
+    pub fn merge(&mut self, other: Config) {
+        for (name, profile) in other.profiles {
+            self.profiles.entry(name).or_default().merge(profile);
+        }
+    }
+
+    /// Profile of the name.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile),
+            None if self.profiles.is_empty() => {
+                bail!(
+                    "Unknown profile: {} (no configuration file defines profiles)",
+                    name
+                )
+            }
+            None => bail!(
+                "Unknown profile: {} (defined: {})",
+                name,
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+/// Configuration files from the lowest priority: `$XDG_CONFIG_HOME/serialcat/config.toml`
+/// (`~/.config` by default) and `./.serialcat.toml`.
+pub fn paths() -> Vec<PathBuf> {
+    paths_from(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"))
+}
+
+fn paths_from(xdg_config_home: Option<OsString>, home: Option<OsString>) -> Vec<PathBuf> {
+    let config_home = xdg_config_home
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| Path::new(&home).join(".config")));
+    config_home
+        .map(|dir| dir.join("serialcat/config.toml"))
+        .into_iter()
+        .chain(Some(PathBuf::from(PROJECT_FILE)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(
+            r#"
+            [profile.nucleo]
+            port = "usb:0483:374b"
+            baud_rate = 921600
+            reconnect = true
+            no-color = false
+            exit-on = ["PASS", "3:^ERROR"]
+            "#,
+        )
+        .unwrap();
+        let profile = config.profile("nucleo").unwrap();
+        assert_eq!(profile.port.as_deref(), Some("usb:0483:374b"));
+        assert_eq!(
+            profile.args(),
+            vec![
+                "--baud-rate=921600",
+                "--exit-on=PASS",
+                "--exit-on=3:^ERROR",
+                "--reconnect"
+            ]
+        );
+        assert!(config.profile("bench").is_err());
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        for text in &[
+            "[profile.a]\nbaud-rate = 1.5",
+            "[profile.a]\nport = 0",
+            "[profile.a]\nprofile = \"b\"",
+            "[profile.a]\nexit-on = [[\"PASS\"]]",
+            "[profile]\na = 1",
+            "profile = 1",
+            "[profiles.a]",
+            "[profile.a",
+        ] {
+            Config::parse(text).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let root = std::env::temp_dir().join(format!("serialcat-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let user = root.join("config.toml");
+        let project = root.join(PROJECT_FILE);
+        fs::write(
+            &user,
+            "[profile.nucleo]\nport = \"usb:0483\"\nbaud-rate = 9600\nreconnect = true\n\
+             [profile.bench]\nbaud-rate = 115200\n",
+        )
+        .unwrap();
+        fs::write(
+            &project,
+            "[profile.nucleo]\nbaud-rate = 921600\nreconnect = false\n",
+        )
+        .unwrap();
+
+        // The project overrides the user option by option
+        let config = Config::load(&[&user, &root.join("missing.toml"), &project]).unwrap();
+        let nucleo = config.profile("nucleo").unwrap();
+        assert_eq!(nucleo.port.as_deref(), Some("usb:0483"));
+        assert_eq!(nucleo.args(), vec!["--baud-rate=921600"]);
+        assert_eq!(
+            config.profile("bench").unwrap().args(),
+            vec!["--baud-rate=115200"]
+        );
+
+        fs::write(&project, "[profile.nucleo]\nbaud-rate = 1.5\n").unwrap();
+        let e = Config::load(&[&user, &project]).unwrap_err();
+        assert!(format!("{:#}", e).starts_with(&format!(
+            "Invalid configuration file: {}: Invalid profile: nucleo: ",
+            project.display()
+        )));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn config_paths() {
+        let project = PathBuf::from(PROJECT_FILE);
+        assert_eq!(
+            paths_from(Some("/xdg".into()), Some("/home/me".into())),
+            vec![PathBuf::from("/xdg/serialcat/config.toml"), project.clone()]
+        );
+        assert_eq!(
+            paths_from(Some("".into()), Some("/home/me".into())),
+            vec![
+                PathBuf::from("/home/me/.config/serialcat/config.toml"),
+                project.clone()
+            ]
+        );
+        assert_eq!(paths_from(None, None), vec![project]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Session control commands
+//!
+//! Commands come from the [escape menu](../escape/index.html) or from the control socket.
+//! The control socket accepts one command per line:
+//!
+//! | Command                                 | Description                     |
+//! |-----------------------------------------|---------------------------------|
+//! | `quit`                                  | Quit the session                |
+//! | `settings`                              | Show the current settings       |
+//! | `display`                               | Cycle output mode               |
+//! | `dtr`                                   | Toggle DTR                      |
+//! | `rts`                                   | Toggle RTS                      |
+//! | `dtr <on\|off>`                         | Set DTR                         |
+//! | `rts <on\|off>`                         | Set RTS                         |
+//! | `reset [dtr\|rts\|esp32\|esp32-boot]`  | Reset the board by DTR and RTS  |
+//! | `break`                                 | Send BREAK                      |
+//! | `baud <BAUD>`                           | Change baud rate                |
+//! | `data-bits <5\|6\|7\|8>`                | Change data bits                |
+//! | `parity <none\|odd\|even\|mark\|space>` | Change parity                   |
+//! | `stop-bits <1\|1.5\|2>`                 | Change stop bits                |
+//! | `flow-control <none\|software\|hardware>` | Change flow control           |
+
+use anyhow::{bail, Result};
+use tokio_serial::{DataBits, FlowControl};
+
+use crate::{
+    opt,
+    port::{LineLevel, Parity, ResetPulse, StopBits},
+};
+
+/// Line setting to cycle through its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineSetting {
+    /// Data bits.
+    DataBits,
+    /// Parity.
+    Parity,
+    /// Stop bits.
+    StopBits,
+    /// Flow control.
+    FlowControl,
+}
+
+/// Command to control the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Quit the session.
+    Quit,
+    /// Show the current settings.
+    ShowSettings,
+    /// Cycle output modes.
+    ToggleDisplay,
+    /// Toggle DTR line.
+    ToggleDtr,
+    /// Toggle RTS line.
+    ToggleRts,
+    /// Set DTR line.
+    SetDtr(bool),
+    /// Set RTS line.
+    SetRts(bool),
+    /// Reset the board by the sequence of DTR and RTS, or the one of `--reset` if `None`.
+    Reset(Option<ResetPulse>),
+    /// Send BREAK.
+    SendBreak,
+    /// Change the baud rate.
+    SetBaudRate(u32),
+    /// Change the data bits.
+    SetDataBits(DataBits),
+    /// Change the parity.
+    SetParity(Parity),
+    /// Change the stop bits.
+    SetStopBits(StopBits),
+    /// Change the flow control.
+    SetFlowControl(FlowControl),
+    /// Change the line setting to the next value.
+    Cycle(LineSetting),
+}
+
+/// Parse a line of the control socket.
+///
+/// ```
+///     # use serialcat::control::{parse, Command};
+///
+///     assert_eq!(parse("baud 921600").unwrap(), Command::SetBaudRate(921_600));
+///     assert!(parse("baud fast").is_err());
+/// ```
+pub fn parse(line: &str) -> Result<Command> {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => bail!("Empty command"),
+    };
+    let arg = words.next();
+    if words.next().is_some() {
+        bail!("Too many arguments: {}", line.trim());
+    }
+
+    let command = match (name, arg) {
+        ("quit", None) => Command::Quit,
+        ("settings", None) => Command::ShowSettings,
+        ("display", None) => Command::ToggleDisplay,
+        ("dtr", None) => Command::ToggleDtr,
+        ("rts", None) => Command::ToggleRts,
+        ("dtr", Some(arg)) => Command::SetDtr(level_from_str(arg)?),
+        ("rts", Some(arg)) => Command::SetRts(level_from_str(arg)?),
+        ("reset", None) => Command::Reset(None),
+        ("reset", Some(arg)) => Command::Reset(Some(opt::reset_pulse_from_str(arg)?)),
+        ("break", None) => Command::SendBreak,
+        ("baud", Some(arg)) => match arg.parse() {
+            Ok(baud_rate) if baud_rate > 0 => Command::SetBaudRate(baud_rate),
+            _ => bail!("Invalid baud rate: {}", arg),
+        },
+        ("data-bits", Some(arg)) => Command::SetDataBits(opt::data_bits_from_str(arg)?),
+        ("parity", Some(arg)) => Command::SetParity(opt::parity_from_str(arg)?),
+        ("stop-bits", Some(arg)) => Command::SetStopBits(opt::stop_bits_from_str(arg)?),
+        ("flow-control", Some(arg)) => Command::SetFlowControl(opt::flow_control_from_str(arg)?),
+        ("quit", _) | ("settings", _) | ("display", _) | ("break", _) => {
+            bail!("Command takes no argument: {}", name)
+        }
+        ("baud", None)
+        | ("data-bits", None)
+        | ("parity", None)
+        | ("stop-bits", None)
+        | ("flow-control", None) => bail!("Command needs an argument: {}", name),
+        _ => bail!("Unknown command: {}", name),
+    };
+    Ok(command)
+}
+
+/// Level to set a modem line to. `keep` is only for opening the port.
+fn level_from_str(s: &str) -> Result<bool> {
+    match opt::line_level_from_str(s)? {
+        LineLevel::On => Ok(true),
+        LineLevel::Off => Ok(false),
+        LineLevel::Keep => bail!("Line level keep is only for opening the port"),
+    }
+}
+
+/// Next value of data bits, used by [Cycle](enum.Command.html#variant.Cycle).
+pub fn next_data_bits(data_bits: DataBits) -> DataBits {
+    match data_bits {
+        DataBits::Five => DataBits::Six,
+        DataBits::Six => DataBits::Seven,
+        DataBits::Seven => DataBits::Eight,
+        DataBits::Eight => DataBits::Five,
+    }
+}
+
+/// Next value of parity, used by [Cycle](enum.Command.html#variant.Cycle).
+pub fn next_parity(parity: Parity) -> Parity {
+    match parity {
+        Parity::None => Parity::Even,
+        Parity::Even => Parity::Odd,
+        Parity::Odd => Parity::Mark,
+        Parity::Mark => Parity::Space,
+        Parity::Space => Parity::None,
+    }
+}
+
+/// Next value of stop bits, used by [Cycle](enum.Command.html#variant.Cycle).
+pub fn next_stop_bits(stop_bits: StopBits) -> StopBits {
+    match stop_bits {
+        StopBits::One => StopBits::Two,
+        StopBits::OnePointFive | StopBits::Two => StopBits::One,
+    }
+}
+
+/// Next value of flow control, used by [Cycle](enum.Command.html#variant.Cycle).
+pub fn next_flow_control(flow_control: FlowControl) -> FlowControl {
+    match flow_control {
+        FlowControl::None => FlowControl::Software,
+        FlowControl::Software => FlowControl::Hardware,
+        FlowControl::Hardware => FlowControl::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        for (line, command) in &[
+            ("quit", Command::Quit),
+            ("settings", Command::ShowSettings),
+            ("display", Command::ToggleDisplay),
+            ("dtr", Command::ToggleDtr),
+            ("rts", Command::ToggleRts),
+            ("dtr off", Command::SetDtr(false)),
+            ("rts on", Command::SetRts(true)),
+            ("reset", Command::Reset(None)),
+            (
+                "reset esp32-boot",
+                Command::Reset(Some(ResetPulse::Esp32Boot)),
+            ),
+            ("break", Command::SendBreak),
+            ("baud 115200", Command::SetBaudRate(115_200)),
+            ("  baud\t250000 \n", Command::SetBaudRate(250_000)),
+            ("data-bits 7", Command::SetDataBits(DataBits::Seven)),
+            ("parity even", Command::SetParity(Parity::Even)),
+            ("parity mark", Command::SetParity(Parity::Mark)),
+            ("parity space", Command::SetParity(Parity::Space)),
+            ("stop-bits 2", Command::SetStopBits(StopBits::Two)),
+            (
+                "stop-bits 1.5",
+                Command::SetStopBits(StopBits::OnePointFive),
+            ),
+            (
+                "flow-control hardware",
+                Command::SetFlowControl(FlowControl::Hardware),
+            ),
+        ] {
+            assert_eq!(parse(line).unwrap(), *command);
+        }
+
+        for line in &[
+            "",
+            "reboot",
+            "quit now",
+            "dtr keep",
+            "rts low",
+            "reset en",
+            "baud",
+            "baud 0",
+            "baud -1",
+            "baud 9600 8",
+            "data-bits 9",
+            "parity crc",
+            "stop-bits 3",
+            "flow-control rts",
+        ] {
+            parse(line).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        let mut data_bits = DataBits::Eight;
+        for _ in 0..4 {
+            data_bits = next_data_bits(data_bits);
+        }
+        assert_eq!(data_bits, DataBits::Eight);
+        assert_eq!(next_parity(Parity::None), Parity::Even);
+        assert_eq!(next_parity(Parity::Space), Parity::None);
+        assert_eq!(next_stop_bits(StopBits::One), StopBits::Two);
+        assert_eq!(next_flow_control(FlowControl::Hardware), FlowControl::None);
+    }
+}
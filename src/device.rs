@@ -0,0 +1,766 @@
+//! Serial devices in sysfs
+
+use anyhow::{anyhow, bail, Error, Result};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const SYS_CLASS_TTY: &str = "/sys/class/tty";
+const DEV: &str = "/dev";
+
+/// Serial port found in sysfs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PortInfo {
+    /// Path of the port, such as `/dev/ttyACM0`.
+    pub path: String,
+    /// Bus of the port: `usb`, `pci` or `platform`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// USB vendor ID.
+    #[serde(serialize_with = "serialize_id")]
+    pub vid: Option<u16>,
+    /// USB product ID.
+    #[serde(serialize_with = "serialize_id")]
+    pub pid: Option<u16>,
+    /// USB manufacturer string.
+    pub manufacturer: Option<String>,
+    /// USB product string.
+    pub product: Option<String>,
+    /// USB serial number.
+    pub serial_number: Option<String>,
+    /// Persistent path in `/dev/serial/by-id`.
+    pub by_id: Option<String>,
+}
+
+/// List serial ports, sorted by the path.
+///
+/// Virtual terminals and UARTs without hardware are not serial ports.
+pub fn list() -> Vec<PortInfo> {
+    list_in(Path::new(SYS_CLASS_TTY), Path::new(DEV))
+}
+
+fn list_in(sys_class_tty: &Path, dev: &Path) -> Vec<PortInfo> {
+    let by_id = fs::read_dir(dev.join("serial/by-id"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let link = entry.ok()?.path();
+                    Some((fs::canonicalize(&link).ok()?, link))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut names = tty_names(sys_class_tty);
+    names.sort_by(|a, b| natural_key(a).cmp(&natural_key(b)));
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let tty = sys_class_tty.join(&name);
+            let device = fs::canonicalize(tty.join("device")).ok()?;
+            // 8250 UARTs are registered even without hardware
+            if attribute(&tty, "type").as_deref() == Some("0") {
+                return None;
+            }
+
+            let path = dev.join(&name);
+            let by_id = fs::canonicalize(&path).ok().and_then(|path| {
+                by_id
+                    .iter()
+                    .find(|(target, _)| *target == path)
+                    .map(|(_, link)| link.to_string_lossy().into_owned())
+            });
+            let mut info = PortInfo {
+                path: path.to_string_lossy().into_owned(),
+                kind: "platform",
+                by_id,
+                ..PortInfo::default()
+            };
+            match usb_device(sys_class_tty, &name) {
+                Some(usb) => {
+                    let id = |name| u16::from_str_radix(&attribute(&usb, name)?, 16).ok();
+                    info.kind = "usb";
+                    info.vid = id("idVendor");
+                    info.pid = id("idProduct");
+                    info.manufacturer = attribute(&usb, "manufacturer");
+                    info.product = attribute(&usb, "product");
+                    info.serial_number = attribute(&usb, "serial");
+                }
+                None => {
+                    let pci = device.ancestors().any(|dir| {
+                        fs::read_link(dir.join("subsystem"))
+                            .map(|subsystem| subsystem.ends_with("pci"))
+                            .unwrap_or(false)
+                    });
+                    if pci {
+                        info.kind = "pci";
+                    }
+                }
+            }
+            Some(info)
+        })
+        .collect()
+}
+
+/// Format ports as a table, with `-` for missing values.
+pub fn format_table(ports: &[PortInfo]) -> String {
+    let rows = ports
+        .iter()
+        .map(|port| {
+            let id = match (port.vid, port.pid) {
+                (Some(vid), Some(pid)) => Some(format!("{:04x}:{:04x}", vid, pid)),
+                _ => None,
+            };
+            vec![
+                port.path.clone(),
+                port.kind.to_owned(),
+                id.unwrap_or_else(|| "-".to_owned()),
+                port.manufacturer.clone().unwrap_or_else(|| "-".to_owned()),
+                port.product.clone().unwrap_or_else(|| "-".to_owned()),
+                port.serial_number.clone().unwrap_or_else(|| "-".to_owned()),
+                port.by_id.clone().unwrap_or_else(|| "-".to_owned()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        "PORT",
+        "TYPE",
+        "VID:PID",
+        "MANUFACTURER",
+        "PRODUCT",
+        "SERIAL",
+        "BY-ID",
+    ];
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(title.len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let mut table = String::new();
+    let header = header.iter().map(|title| title.to_string()).collect();
+    for row in Some(header).into_iter().chain(rows) {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(&widths) {
+            let _ = write!(line, "{:width$}  ", cell, width = width);
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Format ports as a JSON array of objects, with `null` for missing values.
+pub fn format_json(ports: &[PortInfo]) -> String {
+    let mut json = serde_json::to_string_pretty(ports).expect("ports are always serializable");
+    json.push('\n');
+    json
+}
+
+/// Serialize a USB ID in hex as `lsusb` shows.
+fn serialize_id<S>(id: &Option<u16>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match id {
+        Some(id) => serializer.serialize_str(&format!("{:04x}", id)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Way to select a serial port, parsed from a `PORT` argument.
+///
+/// ```
+///     # use serialcat::device::Selector;
+///
+///     let usb: Selector = "usb:0483:374b".parse().unwrap();
+///     assert_eq!(usb, Selector::Usb(0x0483, Some(0x374b)));
+///     let glob: Selector = "/dev/ttyUSB*".parse().unwrap();
+///     assert_eq!(glob, Selector::Glob("/dev/ttyUSB*".to_owned()));
+///     let path: Selector = "/dev/ttyACM0".parse().unwrap();
+///     assert_eq!(path, Selector::Path("/dev/ttyACM0".to_owned()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// Exact path of the port, such as `/dev/ttyACM0`.
+    Path(String),
+    /// Glob of the path or the by-id path, such as `/dev/ttyUSB*`.
+    Glob(String),
+    /// USB vendor ID and optionally product ID: `usb:VID[:PID]` in hex.
+    Usb(u16, Option<u16>),
+    /// Glob of the USB serial number: `serial:GLOB`.
+    SerialNumber(String),
+    /// Glob of the file name in `/dev/serial/by-id`: `by-id:GLOB`.
+    ById(String),
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Selector> {
+        if let Some(ids) = s.strip_prefix("usb:") {
+            let id = |id: &str| match u16::from_str_radix(id, 16) {
+                Ok(n) if id.bytes().all(|b| b.is_ascii_hexdigit()) => Ok(n),
+                _ => bail!("Invalid USB ID: {}", id),
+            };
+            return match ids.find(':') {
+                Some(i) => Ok(Selector::Usb(id(&ids[..i])?, Some(id(&ids[i + 1..])?))),
+                None => Ok(Selector::Usb(id(ids)?, None)),
+            };
+        }
+        let (selector, pattern): (fn(String) -> Selector, _) =
+            if let Some(pattern) = s.strip_prefix("serial:") {
+                (Selector::SerialNumber, pattern)
+            } else if let Some(pattern) = s.strip_prefix("by-id:") {
+                (Selector::ById, pattern)
+            } else if s.contains(&['*', '?', '['][..]) {
+                (Selector::Glob, s)
+            } else if s.is_empty() {
+                bail!("Empty serial port");
+            } else {
+                return Ok(Selector::Path(s.to_owned()));
+            };
+        if pattern.is_empty() {
+            bail!("Empty pattern: {}", s);
+        }
+        Ok(selector(pattern.to_owned()))
+    }
+}
+
+impl Selector {
+    /// Whether the selector matches the port.
+    pub fn matches(&self, port: &PortInfo) -> bool {
+        match self {
+            Selector::Path(path) => port.path == *path || port.by_id.as_ref() == Some(path),
+            Selector::Glob(pattern) => {
+                glob_match(pattern, &port.path)
+                    || port
+                        .by_id
+                        .as_ref()
+                        .map(|by_id| glob_match(pattern, by_id))
+                        .unwrap_or(false)
+            }
+            Selector::Usb(vid, pid) => {
+                port.vid == Some(*vid) && pid.map(|pid| port.pid == Some(pid)).unwrap_or(true)
+            }
+            Selector::SerialNumber(pattern) => port
+                .serial_number
+                .as_ref()
+                .map(|serial_number| glob_match(pattern, serial_number))
+                .unwrap_or(false),
+            Selector::ById(pattern) => port
+                .by_id
+                .as_ref()
+                .and_then(|by_id| Path::new(by_id).file_name()?.to_str())
+                .map(|name| glob_match(pattern, name))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Paths of the ports selected, sorted by the path.
+    ///
+    /// An exact path is selected as is if it exists, even if it is not listed by [list](fn.list.html).
+    pub fn find(&self) -> Vec<String> {
+        self.find_in(Path::new(SYS_CLASS_TTY), Path::new(DEV))
+    }
+
+    /// Path of the only port selected, or `None` if no port is selected.
+    ///
+    /// Fails when several ports are selected.
+    pub fn find_one(&self) -> Result<Option<String>> {
+        self.find_one_in(Path::new(SYS_CLASS_TTY), Path::new(DEV))
+    }
+
+    fn find_one_in(&self, sys_class_tty: &Path, dev: &Path) -> Result<Option<String>> {
+        let mut paths = self.find_in(sys_class_tty, dev);
+        if paths.len() > 1 {
+            bail!("{} serial ports match: {}", paths.len(), paths.join(", "));
+        }
+        Ok(paths.pop())
+    }
+
+    fn find_in(&self, sys_class_tty: &Path, dev: &Path) -> Vec<String> {
+        match self {
+            Selector::Path(path) if Path::new(path).exists() => vec![path.clone()],
+            Selector::Path(_) => Vec::new(),
+            _ => list_in(sys_class_tty, dev)
+                .into_iter()
+                .filter(|port| self.matches(port))
+                .map(|port| port.path)
+                .collect(),
+        }
+    }
+}
+
+/// Resolve the selector, such as `usb:0483:374b`, to the path of exactly one serial port.
+///
+/// An exact path is returned as is, to open even a port not in sysfs such as a pseudo terminal.
+/// Fails when no port or several ports match. The message does not repeat the selector.
+pub fn resolve(selector: &str) -> Result<String> {
+    resolve_in(Path::new(SYS_CLASS_TTY), Path::new(DEV), selector)
+}
+
+fn resolve_in(sys_class_tty: &Path, dev: &Path, selector: &str) -> Result<String> {
+    match selector.parse::<Selector>()? {
+        Selector::Path(path) => Ok(path),
+        selector => selector
+            .find_one_in(sys_class_tty, dev)?
+            .ok_or_else(|| anyhow!("No serial port matches")),
+    }
+}
+
+/// Whether the text matches the glob pattern of `*`, `?` and `[...]`, as the shell does.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn class_matches(class: &[char], c: char) -> bool {
+        let (negated, class) = match class.split_first() {
+            Some(('!', rest)) | Some(('^', rest)) => (true, rest),
+            _ => (false, class),
+        };
+        let mut i = 0;
+        let mut matched = false;
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == '-' {
+                matched |= (class[i]..=class[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                matched |= class[i] == c;
+                i += 1;
+            }
+        }
+        matched != negated
+    }
+
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            Some(('?', rest)) => !text.is_empty() && matches(rest, &text[1..]),
+            Some(('[', rest)) => {
+                // `]` right after `[` or `[!` is a member, not the end
+                let start = match rest.first() {
+                    Some('!') | Some('^') => 2,
+                    _ => 1,
+                };
+                let end = rest
+                    .iter()
+                    .skip(start)
+                    .position(|&c| c == ']')
+                    .map(|i| i + start);
+                match (end, text.split_first()) {
+                    (Some(end), Some((&c, text))) => {
+                        class_matches(&rest[..end], c) && matches(&rest[end + 1..], text)
+                    }
+                    (Some(_), None) => false,
+                    // Unclosed `[` is a literal
+                    (None, _) => text.first() == Some(&'[') && matches(rest, &text[1..]),
+                }
+            }
+            Some((p, rest)) => text.first() == Some(p) && matches(rest, &text[1..]),
+        }
+    }
+
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    matches(&pattern, &text)
+}
+
+fn tty_names(sys_class_tty: &Path) -> Vec<String> {
+    fs::read_dir(sys_class_tty)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Key to sort `ttyUSB2` before `ttyUSB10`.
+fn natural_key(name: &str) -> (&str, u64) {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (prefix, number) = name.split_at(name.len() - digits);
+    (prefix, number.parse().unwrap_or(0))
+}
+
+/// Serial number of the USB device providing the serial port, such as `/dev/ttyACM0`.
+///
+/// `None` if the port is not a USB device or the device has no serial number.
+pub fn usb_serial_number(port: &str) -> Option<String> {
+    usb_serial_number_in(Path::new(SYS_CLASS_TTY), Path::new(port))
+}
+
+/// Path of the serial port provided by the USB device with the serial number.
+pub fn find_by_usb_serial_number(serial_number: &str) -> Option<String> {
+    find_by_usb_serial_number_in(Path::new(SYS_CLASS_TTY), Path::new(DEV), serial_number)
+}
+
+/// Directory of the USB device providing the tty, which has attributes such as `idVendor`.
+fn usb_device(sys_class_tty: &Path, name: &str) -> Option<PathBuf> {
+    let device = fs::canonicalize(sys_class_tty.join(name).join("device")).ok()?;
+    device
+        .ancestors()
+        .find(|dir| dir.join("idVendor").is_file())
+        .map(Path::to_owned)
+}
+
+fn attribute(device: &Path, name: &str) -> Option<String> {
+    let value = fs::read_to_string(device.join(name)).ok()?;
+    Some(value.trim().to_owned()).filter(|value| !value.is_empty())
+}
+
+fn usb_serial_number_in(sys_class_tty: &Path, port: &Path) -> Option<String> {
+    // Resolve links such as /dev/serial/by-id/*
+    let port = fs::canonicalize(port).ok()?;
+    let name = port.file_name()?.to_str()?;
+    attribute(&usb_device(sys_class_tty, name)?, "serial")
+}
+
+fn find_by_usb_serial_number_in(
+    sys_class_tty: &Path,
+    dev: &Path,
+    serial_number: &str,
+) -> Option<String> {
+    let mut names = tty_names(sys_class_tty);
+    names.sort_by(|a, b| natural_key(a).cmp(&natural_key(b)));
+    names
+        .into_iter()
+        .find(|name| {
+            usb_device(sys_class_tty, name)
+                .and_then(|device| attribute(&device, "serial"))
+                .as_deref()
+                == Some(serial_number)
+        })
+        .map(|name| dev.join(name).to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Build sysfs and /dev with serial ports of each type, and return the root.
+    fn fake_ports(test: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("serialcat-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let sys_class_tty = root.join("sys/class/tty");
+        let dev = root.join("dev");
+        fs::create_dir_all(&sys_class_tty).unwrap();
+        fs::create_dir_all(dev.join("serial/by-id")).unwrap();
+        let tty = |device: &Path, name: &str| {
+            let dir = device.join("tty").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            symlink(device, dir.join("device")).unwrap();
+            symlink(&dir, sys_class_tty.join(name)).unwrap();
+            fs::write(dev.join(name), "").unwrap();
+            dir
+        };
+
+        // USB serial adapters, numbered past 9
+        let pci = root.join("sys/devices/pci0000:00/0000:00:14.0");
+        fs::create_dir_all(&pci).unwrap();
+        fs::create_dir_all(root.join("sys/bus/pci")).unwrap();
+        symlink(root.join("sys/bus/pci"), pci.join("subsystem")).unwrap();
+        for (i, serial) in &[(2, Some("A10K3X9")), (10, None)] {
+            let usb = pci.join(format!("usb1/1-{}", i));
+            let interface = usb.join(format!("1-{}:1.0", i));
+            fs::create_dir_all(&interface).unwrap();
+            fs::write(usb.join("idVendor"), "0403\n").unwrap();
+            fs::write(usb.join("idProduct"), "6001\n").unwrap();
+            fs::write(usb.join("manufacturer"), "FTDI\n").unwrap();
+            fs::write(usb.join("product"), "FT232R \"USB\" UART\n").unwrap();
+            if let Some(serial) = serial {
+                fs::write(usb.join("serial"), format!("{}\n", serial)).unwrap();
+            }
+            tty(&interface, &format!("ttyUSB{}", i));
+        }
+        symlink(
+            dev.join("ttyUSB2"),
+            dev.join("serial/by-id/usb-FTDI_FT232R_USB_UART_A10K3X9-if00-port0"),
+        )
+        .unwrap();
+
+        // A PCI UART, a platform UART, a UART without hardware, and a virtual terminal
+        let exar = pci.join("0000:02:00.0");
+        fs::create_dir_all(&exar).unwrap();
+        symlink(root.join("sys/bus/pci"), exar.join("subsystem")).unwrap();
+        tty(&exar, "ttyS4");
+        let uart = root.join("sys/devices/platform/serial8250");
+        fs::write(tty(&uart, "ttyS0").join("type"), "4\n").unwrap();
+        fs::write(tty(&uart, "ttyS1").join("type"), "0\n").unwrap();
+        let tty0 = root.join("sys/devices/virtual/tty/tty0");
+        fs::create_dir_all(&tty0).unwrap();
+        symlink(&tty0, sys_class_tty.join("tty0")).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn list_ports() {
+        let root = fake_ports("list");
+        let sys_class_tty = root.join("sys/class/tty");
+        let dev = root.join("dev");
+        let path = |name: &str| dev.join(name).to_string_lossy().into_owned();
+        let ftdi = PortInfo {
+            kind: "usb",
+            vid: Some(0x0403),
+            pid: Some(0x6001),
+            manufacturer: Some("FTDI".to_owned()),
+            product: Some("FT232R \"USB\" UART".to_owned()),
+            ..PortInfo::default()
+        };
+        let ports = vec![
+            PortInfo {
+                path: path("ttyS0"),
+                kind: "platform",
+                ..PortInfo::default()
+            },
+            PortInfo {
+                path: path("ttyS4"),
+                kind: "pci",
+                ..PortInfo::default()
+            },
+            PortInfo {
+                path: path("ttyUSB2"),
+                serial_number: Some("A10K3X9".to_owned()),
+                by_id: Some(path(
+                    "serial/by-id/usb-FTDI_FT232R_USB_UART_A10K3X9-if00-port0",
+                )),
+                ..ftdi.clone()
+            },
+            PortInfo {
+                path: path("ttyUSB10"),
+                ..ftdi
+            },
+        ];
+        assert_eq!(list_in(&sys_class_tty, &dev), ports);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn usb_serial_numbers() {
+        let root = fake_ports("serial-numbers");
+        let sys_class_tty = root.join("sys/class/tty");
+        let dev = root.join("dev");
+
+        for port in &[
+            "ttyUSB2",
+            "serial/by-id/usb-FTDI_FT232R_USB_UART_A10K3X9-if00-port0",
+        ] {
+            assert_eq!(
+                usb_serial_number_in(&sys_class_tty, &dev.join(port)).as_deref(),
+                Some("A10K3X9")
+            );
+        }
+        for port in &["ttyUSB10", "ttyS0", "ttyACM0"] {
+            assert_eq!(usb_serial_number_in(&sys_class_tty, &dev.join(port)), None);
+        }
+
+        assert_eq!(
+            find_by_usb_serial_number_in(&sys_class_tty, &dev, "A10K3X9"),
+            Some(dev.join("ttyUSB2").to_string_lossy().into_owned())
+        );
+        assert_eq!(
+            find_by_usb_serial_number_in(&sys_class_tty, &dev, "0123"),
+            None
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn select_ports() {
+        let root = fake_ports("select");
+        let sys_class_tty = root.join("sys/class/tty");
+        let dev = root.join("dev");
+        let path = |name: &str| dev.join(name).to_string_lossy().into_owned();
+        let resolve = |selector: &str| resolve_in(&sys_class_tty, &dev, selector);
+
+        let both: &[&str] = &["ttyUSB2", "ttyUSB10"];
+        for (selector, ports) in &[
+            ("usb:0403:6001", both),
+            ("usb:0403", both),
+            ("serial:A10K3X9", &["ttyUSB2"]),
+            ("serial:a10*", &[]),
+            ("serial:A10*", &["ttyUSB2"]),
+            ("by-id:*FT232R*", &["ttyUSB2"]),
+            (
+                "by-id:usb-FTDI_FT232R_USB_UART_A10K3X9-if00-port0",
+                &["ttyUSB2"],
+            ),
+            ("by-id:*STLink*", &[]),
+            ("usb:0483:374b", &[]),
+        ] {
+            let found = selector
+                .parse::<Selector>()
+                .unwrap()
+                .find_in(&sys_class_tty, &dev);
+            let ports = ports.iter().map(|port| path(port)).collect::<Vec<_>>();
+            assert_eq!(found, ports, "{}", selector);
+        }
+
+        assert_eq!(resolve("serial:A10K3X9").unwrap(), path("ttyUSB2"));
+        assert_eq!(
+            resolve(&format!("{}/ttyS[!0-3]", dev.display())).unwrap(),
+            path("ttyS4")
+        );
+        assert_eq!(
+            resolve(&format!("{}/serial/by-id/*-if00-*", dev.display())).unwrap(),
+            path("ttyUSB2")
+        );
+        // Exact paths are not looked up
+        assert_eq!(resolve("/dev/pts/3").unwrap(), "/dev/pts/3");
+        assert_eq!(
+            resolve("usb:0483:374b").unwrap_err().to_string(),
+            "No serial port matches"
+        );
+        assert_eq!(
+            resolve("usb:0403:6001").unwrap_err().to_string(),
+            format!(
+                "2 serial ports match: {}, {}",
+                path("ttyUSB2"),
+                path("ttyUSB10")
+            )
+        );
+        let exact = Selector::Path(path("ttyS0"));
+        assert_eq!(
+            exact.find_one_in(&sys_class_tty, &dev).unwrap(),
+            Some(path("ttyS0"))
+        );
+        let exact = Selector::Path(path("ttyS9"));
+        assert_eq!(exact.find_one_in(&sys_class_tty, &dev).unwrap(), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn selectors() {
+        for (arg, selector) in &[
+            ("usb:0483:374B", Selector::Usb(0x0483, Some(0x374b))),
+            ("usb:2e8a", Selector::Usb(0x2e8a, None)),
+            (
+                "serial:0671FF*",
+                Selector::SerialNumber("0671FF*".to_owned()),
+            ),
+            ("by-id:*STLink*", Selector::ById("*STLink*".to_owned())),
+            ("/dev/ttyUSB?", Selector::Glob("/dev/ttyUSB?".to_owned())),
+            (
+                "/dev/ttyS[0-3]",
+                Selector::Glob("/dev/ttyS[0-3]".to_owned()),
+            ),
+            ("/dev/ttyACM0", Selector::Path("/dev/ttyACM0".to_owned())),
+            ("COM3", Selector::Path("COM3".to_owned())),
+        ] {
+            assert_eq!(arg.parse::<Selector>().unwrap(), *selector);
+        }
+        for arg in &[
+            "",
+            "usb:",
+            "usb:0483:",
+            "usb:10000",
+            "usb:+483",
+            "usb:xyz",
+            "serial:",
+            "by-id:",
+        ] {
+            arg.parse::<Selector>().unwrap_err();
+        }
+
+        for (pattern, text, matches) in &[
+            ("*", "", true),
+            ("ttyUSB*", "ttyUSB10", true),
+            ("ttyUSB?", "ttyUSB10", false),
+            (
+                "*STLink*",
+                "usb-STMicroelectronics_STLink-V3_0671FF-if02",
+                true,
+            ),
+            ("tty[AU]*", "ttyACM0", true),
+            ("tty[!AU]*", "ttyACM0", false),
+            ("ttyS[0-3]", "ttyS2", true),
+            ("ttyS[0-3]", "ttyS4", false),
+            ("[]]", "]", true),
+            ("tty[", "tty[", true),
+            ("tty", "ttyS0", false),
+        ] {
+            assert_eq!(glob_match(pattern, text), *matches, "{} {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let ports = vec![
+            PortInfo {
+                path: "/dev/ttyACM0".to_owned(),
+                kind: "usb",
+                vid: Some(0x2e8a),
+                pid: Some(0x000a),
+                manufacturer: Some("Raspberry Pi".to_owned()),
+                product: Some("Pico".to_owned()),
+                serial_number: Some("E6614C311B".to_owned()),
+                by_id: Some("/dev/serial/by-id/usb-Raspberry_Pi_Pico_E6614C311B-if00".to_owned()),
+            },
+            PortInfo {
+                path: "/dev/ttyS0".to_owned(),
+                kind: "platform",
+                ..PortInfo::default()
+            },
+        ];
+
+        assert_eq!(
+            format_table(&ports),
+            concat!(
+                "PORT          TYPE      VID:PID    MANUFACTURER  PRODUCT  SERIAL      BY-ID\n",
+                "/dev/ttyACM0  usb       2e8a:000a  Raspberry Pi  Pico     E6614C311B  /dev/serial/by-id/usb-Raspberry_Pi_Pico_E6614C311B-if00\n",
+                "/dev/ttyS0    platform  -          -             -        -           -\n",
+            )
+        );
+        assert_eq!(
+            format_table(&[]),
+            "PORT  TYPE  VID:PID  MANUFACTURER  PRODUCT  SERIAL  BY-ID\n"
+        );
+
+        assert_eq!(
+            format_json(&ports),
+            concat!(
+                "[\n",
+                "  {\n",
+                "    \"path\": \"/dev/ttyACM0\",\n",
+                "    \"type\": \"usb\",\n",
+                "    \"vid\": \"2e8a\",\n",
+                "    \"pid\": \"000a\",\n",
+                "    \"manufacturer\": \"Raspberry Pi\",\n",
+                "    \"product\": \"Pico\",\n",
+                "    \"serial_number\": \"E6614C311B\",\n",
+                "    \"by_id\": \"/dev/serial/by-id/usb-Raspberry_Pi_Pico_E6614C311B-if00\"\n",
+                "  },\n",
+                "  {\n",
+                "    \"path\": \"/dev/ttyS0\",\n",
+                "    \"type\": \"platform\",\n",
+                "    \"vid\": null,\n",
+                "    \"pid\": null,\n",
+                "    \"manufacturer\": null,\n",
+                "    \"product\": null,\n",
+                "    \"serial_number\": null,\n",
+                "    \"by_id\": null\n",
+                "  }\n",
+                "]\n",
+            )
+        );
+        assert_eq!(format_json(&[]), "[]\n");
+        let quoted = PortInfo {
+            product: Some("\"A\\B\"\t".to_owned()),
+            ..PortInfo::default()
+        };
+        assert!(format_json(&[quoted]).contains(r#""product": "\"A\\B\"\t""#));
+    }
+}
@@ -0,0 +1,343 @@
+//! Text encodings of serial ports
+
+use anyhow::{bail, Error, Result};
+use bytes::{BufMut, BytesMut};
+use encoding_rs::EncoderResult;
+use std::{fmt, str::FromStr};
+
+use crate::util::{GetChars, GetCharsMixin as _};
+
+/// Text encoding of bytes sent and received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8.
+    Utf8,
+    /// Shift_JIS.
+    ShiftJis,
+    /// EUC-JP. JIS X 0212 is decoded, but not encoded.
+    EucJp,
+    /// ISO-8859-1, which maps every byte to the same code point.
+    Latin1,
+    /// Code page 437 of IBM PC. Bytes below 0x80 are ASCII.
+    Cp437,
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Encoding> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "shift_jis" | "sjis" => Ok(Encoding::ShiftJis),
+            "euc-jp" | "eucjp" => Ok(Encoding::EucJp),
+            "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "cp437" => Ok(Encoding::Cp437),
+            _ => bail!("Unknown encoding: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::ShiftJis => "shift_jis",
+            Encoding::EucJp => "euc-jp",
+            Encoding::Latin1 => "latin1",
+            Encoding::Cp437 => "cp437",
+        })
+    }
+}
+
+/// Result of [Encoding::decode](enum.Encoding.html#method.decode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded {
+    /// A character and its length in bytes.
+    Char(char, usize),
+    /// The first byte is invalid.
+    Invalid,
+    /// The bytes are the beginning of a character.
+    Incomplete,
+}
+
+/// Characters of code page 437 from 0x80.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+impl Encoding {
+    /// Decode the first character of the bytes.
+    ///
+    /// ```
+    ///     # use serialcat::encoding::{Decoded, Encoding};
+    ///
+    ///     assert_eq!(Encoding::ShiftJis.decode(b"\x82\xa0!"), Decoded::Char('あ', 2));
+    ///     assert_eq!(Encoding::ShiftJis.decode(b"\x82"), Decoded::Incomplete);
+    ///     assert_eq!(Encoding::ShiftJis.decode(b"\x82\x20"), Decoded::Invalid);
+    /// ```
+    pub fn decode(self, bytes: &[u8]) -> Decoded {
+        let b = match bytes.first() {
+            Some(&b) => b,
+            None => return Decoded::Incomplete,
+        };
+        if b < 0x80 && self != Encoding::Utf8 {
+            return Decoded::Char(b as char, 1);
+        }
+
+        match self {
+            Encoding::Utf8 => decode_utf8(bytes),
+            Encoding::ShiftJis => match b {
+                0x81..=0x9f | 0xe0..=0xfc => {
+                    decode_multibyte(self, bytes, &[|b| matches!(b, 0x40..=0x7e | 0x80..=0xfc)])
+                }
+                _ => decode_multibyte(self, bytes, &[]),
+            },
+            Encoding::EucJp => {
+                let trail = |b| matches!(b, 0xa1..=0xfe);
+                match b {
+                    0x8e => decode_multibyte(self, bytes, &[|b| matches!(b, 0xa1..=0xdf)]),
+                    0x8f => decode_multibyte(self, bytes, &[trail, trail]),
+                    0xa1..=0xfe => decode_multibyte(self, bytes, &[trail]),
+                    _ => Decoded::Invalid,
+                }
+            }
+            Encoding::Latin1 => Decoded::Char(b as char, 1),
+            Encoding::Cp437 => Decoded::Char(CP437[b as usize - 0x80], 1),
+        }
+    }
+
+    /// Encode the character, returns `false` if it is not mappable.
+    ///
+    /// ```
+    ///     # use serialcat::encoding::Encoding;
+    ///
+    ///     let mut output = Vec::new();
+    ///     assert!(Encoding::EucJp.encode('あ', &mut output));
+    ///     assert!(!Encoding::Latin1.encode('あ', &mut output));
+    ///     assert_eq!(output, b"\xa4\xa2");
+    /// ```
+    pub fn encode<B>(self, c: char, output: &mut B) -> bool
+    where
+        B: BufMut,
+    {
+        let mut b = [0; 4];
+        match self {
+            Encoding::Utf8 => output.put_slice(c.encode_utf8(&mut b).as_bytes()),
+            _ if c.is_ascii() => output.put_u8(c as u8),
+            Encoding::Latin1 if (c as u32) < 0x100 => output.put_u8(c as u8),
+            Encoding::Cp437 => match CP437.iter().position(|&x| x == c) {
+                Some(i) => output.put_u8(0x80 + i as u8),
+                None => return false,
+            },
+            Encoding::Latin1 => return false,
+            Encoding::ShiftJis | Encoding::EucJp => {
+                let mut encoder = self.encoding_rs().unwrap().new_encoder();
+                let mut buf = [0; 8];
+                let (result, _, written) = encoder.encode_from_utf8_without_replacement(
+                    c.encode_utf8(&mut b),
+                    &mut buf,
+                    true,
+                );
+                if result != EncoderResult::InputEmpty {
+                    return false;
+                }
+                output.put_slice(&buf[..written]);
+            }
+        }
+        true
+    }
+
+    fn encoding_rs(self) -> Option<&'static encoding_rs::Encoding> {
+        match self {
+            Encoding::ShiftJis => Some(encoding_rs::SHIFT_JIS),
+            Encoding::EucJp => Some(encoding_rs::EUC_JP),
+            _ => None,
+        }
+    }
+}
+
+fn decode_utf8(bytes: &[u8]) -> Decoded {
+    let (str, err) = match std::str::from_utf8(bytes) {
+        Ok(str) => (str, None), // None will not be used
+        Err(e) => {
+            let str = unsafe { std::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) };
+            (str, e.error_len())
+        }
+    };
+
+    if let Some(c) = str.chars().next() {
+        Decoded::Char(c, c.len_utf8())
+    } else if err.is_some() {
+        Decoded::Invalid
+    } else {
+        Decoded::Incomplete
+    }
+}
+
+/// Decode a character of a lead byte and trail bytes in the ranges by the table.
+fn decode_multibyte(encoding: Encoding, bytes: &[u8], trails: &[fn(u8) -> bool]) -> Decoded {
+    let len = 1 + trails.len();
+    for (&b, trail) in bytes[1..].iter().zip(trails) {
+        if !trail(b) {
+            return Decoded::Invalid;
+        }
+    }
+    if bytes.len() < len {
+        return Decoded::Incomplete;
+    }
+
+    let decoded = encoding
+        .encoding_rs()
+        .unwrap()
+        .decode_without_bom_handling_and_without_replacement(&bytes[..len]);
+    match decoded.as_ref().and_then(|s| s.chars().next()) {
+        Some(c) => Decoded::Char(c, len),
+        None => Decoded::Invalid,
+    }
+}
+
+/// Streaming encoder of UTF-8 text to the encoding.
+///
+/// Incomplete UTF-8 sequence is held until the rest comes. Invalid bytes are written as is, and
+/// characters not mappable to the encoding are written as `?`.
+///
+/// ```
+///     # use serialcat::encoding::{Encoder, Encoding};
+///     # use bytes::BytesMut;
+///
+///     let mut encoder = Encoder::new(Encoding::ShiftJis);
+///     let mut output = BytesMut::new();
+///     encoder.encode(b"a\xe3\x81", &mut output);
+///     encoder.encode(b"\x82\xf0\x9f\x98\x80", &mut output);
+///     assert_eq!(&output[..], b"a\x82\xa0?");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    encoding: Encoding,
+    pending: BytesMut,
+}
+
+impl Encoder {
+    /// Create an encoder.
+    pub fn new(encoding: Encoding) -> Encoder {
+        Encoder {
+            encoding,
+            pending: BytesMut::new(),
+        }
+    }
+
+    /// Encode the UTF-8 input and append it to the output.
+    pub fn encode<B>(&mut self, input: &[u8], output: &mut B)
+    where
+        B: BufMut,
+    {
+        if self.encoding == Encoding::Utf8 {
+            output.put_slice(input);
+            return;
+        }
+
+        self.pending.put_slice(input);
+        for ch in self.pending.get_chars() {
+            match ch {
+                GetChars::Char(c) => {
+                    if !self.encoding.encode(c, output) {
+                        output.put_u8(b'?');
+                    }
+                }
+                GetChars::Err(b) => output.put_u8(b),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_encoding() {
+        for (s, encoding) in &[
+            ("utf-8", Encoding::Utf8),
+            ("UTF8", Encoding::Utf8),
+            ("shift_jis", Encoding::ShiftJis),
+            ("sjis", Encoding::ShiftJis),
+            ("euc-jp", Encoding::EucJp),
+            ("latin1", Encoding::Latin1),
+            ("iso-8859-1", Encoding::Latin1),
+            ("cp437", Encoding::Cp437),
+        ] {
+            assert_eq!(s.parse::<Encoding>().unwrap(), *encoding);
+        }
+        "utf-16".parse::<Encoding>().unwrap_err();
+    }
+
+    #[test]
+    fn decode() {
+        for (encoding, bytes, decoded) in &[
+            (Encoding::Utf8, &b"\xce\x93a"[..], Decoded::Char('Γ', 2)),
+            (Encoding::Utf8, b"\xce", Decoded::Incomplete),
+            (Encoding::Utf8, b"\x83", Decoded::Invalid),
+            (Encoding::ShiftJis, b"\xb1", Decoded::Char('ｱ', 1)),
+            (Encoding::ShiftJis, b"\x88\x9f", Decoded::Char('亜', 2)),
+            (Encoding::ShiftJis, b"\xa0", Decoded::Invalid),
+            (Encoding::EucJp, b"\xb0\xa1", Decoded::Char('亜', 2)),
+            (Encoding::EucJp, b"\x8e\xb1", Decoded::Char('ｱ', 2)),
+            (Encoding::EucJp, b"\x8f\xb0", Decoded::Incomplete),
+            (Encoding::EucJp, b"\x8f\xb0\xa1", Decoded::Char('丂', 3)),
+            (Encoding::EucJp, b"\x8f\x41\xa1", Decoded::Invalid),
+            (Encoding::EucJp, b"\xff", Decoded::Invalid),
+            (Encoding::Latin1, b"\xe9", Decoded::Char('é', 1)),
+            (Encoding::Latin1, b"\x85", Decoded::Char('\u{85}', 1)),
+            (Encoding::Cp437, b"\xc9", Decoded::Char('╔', 1)),
+            (Encoding::Cp437, b"\x1b", Decoded::Char('\x1b', 1)),
+        ] {
+            assert_eq!(
+                encoding.decode(bytes),
+                *decoded,
+                "{:?} {:?}",
+                encoding,
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn encode() {
+        for (encoding, text, bytes) in &[
+            (Encoding::Utf8, "aΓ", &b"a\xce\x93"[..]),
+            (Encoding::ShiftJis, "a亜ｱ", b"a\x88\x9f\xb1"),
+            (Encoding::EucJp, "亜ｱ", b"\xb0\xa1\x8e\xb1"),
+            (Encoding::Latin1, "é\u{85}", b"\xe9\x85"),
+            (Encoding::Cp437, "╔═╗", b"\xc9\xcd\xbb"),
+        ] {
+            let mut output = Vec::new();
+            for c in text.chars() {
+                assert!(encoding.encode(c, &mut output));
+            }
+            assert_eq!(&output[..], *bytes);
+        }
+
+        let mut output = Vec::new();
+        assert!(!Encoding::Cp437.encode('ã', &mut output));
+        assert!(!Encoding::ShiftJis.encode('😀', &mut output));
+        assert!(!Encoding::EucJp.encode('丂', &mut output));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn encoder() {
+        let mut encoder = Encoder::new(Encoding::Latin1);
+        let mut output = BytesMut::new();
+        encoder.encode(b"caf\xc3", &mut output);
+        assert_eq!(&output[..], b"caf");
+        encoder.encode(b"\xa9\xff\xe2\x82\xac\r\n", &mut output);
+        assert_eq!(&output[..], b"caf\xe9\xff?\r\n");
+    }
+}
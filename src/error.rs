@@ -0,0 +1,215 @@
+//! Errors classified by the exit status
+//!
+//! Exit statuses of errors follow `sysexits.h`:
+//!
+//! | Status | Meaning                                                |
+//! |--------|--------------------------------------------------------|
+//! | 0      | Quit, or a line matched an `--exit-on` pattern         |
+//! | 1      | A line matched a `--fail-on` pattern                   |
+//! | 64     | Invalid command line                                   |
+//! | 66     | stdin reached EOF in an interactive session            |
+//! | 69     | The serial port cannot be opened                       |
+//! | 70     | Other errors                                           |
+//! | 74     | The serial port disappeared                            |
+//! | 77     | Permission denied to open the serial port              |
+//! | 78     | Invalid configuration file or profile                  |
+//! | 124    | Timed out, by `--timeout`, `--wait` or `expect`        |
+//! | 129    | Terminated by `SIGHUP`                                 |
+//! | 130    | Terminated by `SIGINT`                                 |
+//! | 131    | Terminated by `SIGQUIT`                                |
+//! | 141    | stdout is closed, as if killed by `SIGPIPE`            |
+//! | 143    | Terminated by `SIGTERM`                                |
+
+use std::{fmt, io};
+
+/// Exit status when a `--fail-on` pattern matches.
+pub const EXIT_FAIL: i32 = 1;
+/// Exit status on an invalid command line.
+pub const EX_USAGE: i32 = 64;
+/// Exit status when stdin reached EOF.
+pub const EX_NOINPUT: i32 = 66;
+/// Exit status when the serial port cannot be opened.
+pub const EX_UNAVAILABLE: i32 = 69;
+/// Exit status on errors not classified.
+pub const EX_SOFTWARE: i32 = 70;
+/// Exit status when the serial port disappeared.
+pub const EX_IOERR: i32 = 74;
+/// Exit status when permission denied.
+pub const EX_NOPERM: i32 = 77;
+/// Exit status on invalid configuration files.
+pub const EX_CONFIG: i32 = 78;
+/// Exit status on timeouts, as `timeout(1)` does.
+pub const EXIT_TIMEOUT: i32 = 124;
+/// Exit status on signals, to which the signal number is added as a shell reports.
+pub const EXIT_SIGNAL: i32 = 128;
+/// Exit status when stdout is closed, as a shell reports a process killed by `SIGPIPE`.
+pub const EXIT_PIPE: i32 = EXIT_SIGNAL + libc::SIGPIPE;
+
+/// Failure of a session, which decides the exit status.
+///
+/// Attach it to the `anyhow` chain as the context, so that the message still shows the cause.
+///
+/// ```
+///     # use serialcat::error::{self, Error};
+///     # use anyhow::Context as _;
+///     # use std::io;
+///
+///     let result: Result<(), _> = Err(io::Error::from_raw_os_error(libc::EIO));
+///     let e = result
+///         .context(Error::Disconnected)
+///         .context("An error occurred on reader")
+///         .unwrap_err();
+///     assert_eq!(error::status(&e), error::EX_IOERR);
+///     assert!(format!("{:#}", e).starts_with(
+///         "An error occurred on reader: Serial port disconnected: "
+///     ));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The serial port cannot be opened.
+    OpenPort(String),
+    /// Permission denied to open the serial port.
+    PermissionDenied(String),
+    /// The serial port disappeared, such as an unplugged USB adapter.
+    Disconnected,
+    /// stdout is closed, such as by the reader of the pipe.
+    StdoutClosed,
+    /// stdin reached EOF in an interactive session, such as when the terminal is gone.
+    StdinEof,
+    /// Timed out, with the message.
+    Timeout(String),
+}
+
+impl Error {
+    /// Exit status of the error.
+    pub fn status(&self) -> i32 {
+        match self {
+            Error::OpenPort(_) => EX_UNAVAILABLE,
+            Error::PermissionDenied(_) => EX_NOPERM,
+            Error::Disconnected => EX_IOERR,
+            Error::StdoutClosed => EXIT_PIPE,
+            Error::StdinEof => EX_NOINPUT,
+            Error::Timeout(_) => EXIT_TIMEOUT,
+        }
+    }
+
+    /// Error for a failure to open the serial port at `path`, classified by the cause.
+    pub fn open_port(path: &str, error: &anyhow::Error) -> Error {
+        let denied = error
+            .chain()
+            .filter_map(|e| e.downcast_ref::<io::Error>())
+            .any(is_permission_denied);
+        if denied {
+            Error::PermissionDenied(path.to_owned())
+        } else {
+            Error::OpenPort(path.to_owned())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OpenPort(path) => write!(f, "Cannot open serial port: {}", path),
+            Error::PermissionDenied(path) => {
+                write!(f, "Permission denied to open serial port: {}", path)
+            }
+            Error::Disconnected => write!(f, "Serial port disconnected"),
+            Error::StdoutClosed => write!(f, "stdout is closed"),
+            Error::StdinEof => write!(f, "stdin reached EOF"),
+            Error::Timeout(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Whether the I/O error on the serial port means that the device is gone.
+pub fn is_disconnected(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EIO) | Some(libc::ENXIO) | Some(libc::ENODEV)
+    )
+}
+
+/// Whether the I/O error on opening a file means that permission is denied.
+///
+/// serialport reports `EACCES` only by the description, as an error of other kind.
+pub fn is_permission_denied(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::PermissionDenied
+        || (e.kind() == io::ErrorKind::Other
+            && e.raw_os_error().is_none()
+            && e.to_string() == "Permission denied")
+}
+
+/// Whether the I/O error on stdout means that it is closed.
+pub fn is_closed(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::BrokenPipe
+}
+
+/// Exit status of an error, by the outermost [Error](enum.Error.html) in the chain.
+pub fn status(error: &anyhow::Error) -> i32 {
+    error
+        .downcast_ref::<Error>()
+        .map_or(EX_SOFTWARE, Error::status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context as _};
+
+    #[test]
+    fn statuses() {
+        let e = anyhow!("Invalid script file");
+        assert_eq!(status(&e), EX_SOFTWARE);
+
+        let e = Err::<(), _>(io::Error::from(io::ErrorKind::BrokenPipe))
+            .context(Error::StdoutClosed)
+            .context("An error occurred on reader")
+            .unwrap_err();
+        assert_eq!(status(&e), EXIT_PIPE);
+        assert_eq!(
+            format!("{:#}", e),
+            "An error occurred on reader: stdout is closed: broken pipe"
+        );
+
+        let e = anyhow::Error::new(Error::Timeout("Timed out after 1s".to_owned()));
+        assert_eq!(status(&e), EXIT_TIMEOUT);
+        assert_eq!(e.to_string(), "Timed out after 1s");
+    }
+
+    #[test]
+    fn open_port() {
+        let e = anyhow::Error::new(io::Error::from_raw_os_error(libc::ENOENT));
+        assert_eq!(
+            Error::open_port("/nonexistent/tty", &e),
+            Error::OpenPort("/nonexistent/tty".to_owned())
+        );
+        let open_port = |e: io::Error| {
+            let e = anyhow::Error::new(e).context("Cannot open");
+            Error::open_port("/dev/ttyS0", &e)
+        };
+        let denied = Error::PermissionDenied("/dev/ttyS0".to_owned());
+        assert_eq!(
+            open_port(io::Error::from_raw_os_error(libc::EACCES)),
+            denied
+        );
+        assert_eq!(open_port(io::ErrorKind::PermissionDenied.into()), denied);
+        // As serialport reports errno it does not map
+        let serialport_error = |msg| serialport::Error::new(serialport::ErrorKind::Unknown, msg);
+        assert_eq!(
+            open_port(serialport_error("Permission denied").into()),
+            denied
+        );
+        assert_eq!(
+            open_port(serialport_error("Device or resource busy").into()),
+            Error::OpenPort("/dev/ttyS0".to_owned())
+        );
+        assert!(is_disconnected(&io::Error::from_raw_os_error(libc::EIO)));
+        assert!(!is_disconnected(&io::Error::from(
+            io::ErrorKind::WouldBlock
+        )));
+        assert!(is_closed(&io::Error::from_raw_os_error(libc::EPIPE)));
+    }
+}
@@ -0,0 +1,276 @@
+//! Escape key command menu
+//!
+//! In interactive session, every keystroke is sent to the serial port.
+//! To control `sc` itself, type the escape key and then a command key.
+
+pub use crate::control::Command;
+use crate::control::LineSetting;
+
+/// Result of [EscapeMenu::feed](struct.EscapeMenu.html#method.feed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Send the byte to the serial port.
+    Send(u8),
+    /// Execute the command.
+    Command(Command),
+    /// Show the prompt line. The previous prompt line should be overwritten.
+    Prompt(String),
+    /// Show the message.
+    Notice(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Normal,
+    Escaped,
+    BaudRate(String),
+}
+
+const BAUD_RATE_PROMPT: &str = "baud rate: ";
+
+/// State machine to interpret keystrokes.
+///
+/// ```
+///     # use serialcat::escape::{Command, EscapeMenu, Event};
+///
+///     let mut menu = EscapeMenu::new(0x14); // Ctrl-T
+///     assert_eq!(menu.feed(b'a'), Some(Event::Send(b'a')));
+///     assert_eq!(menu.feed(0x14), None);
+///     assert_eq!(menu.feed(b'q'), Some(Event::Command(Command::Quit)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeMenu {
+    escape: u8,
+    state: State,
+}
+
+impl EscapeMenu {
+    /// Create a menu with the escape key.
+    pub fn new(escape: u8) -> EscapeMenu {
+        EscapeMenu {
+            escape,
+            state: State::Normal,
+        }
+    }
+
+    /// Feed a keystroke.
+    ///
+    /// Returns `None` if the keystroke was consumed by the menu without any event.
+    pub fn feed(&mut self, byte: u8) -> Option<Event> {
+        match std::mem::replace(&mut self.state, State::Normal) {
+            State::Normal => {
+                if byte == self.escape {
+                    self.state = State::Escaped;
+                    None
+                } else {
+                    Some(Event::Send(byte))
+                }
+            }
+            State::Escaped => self.command(byte),
+            State::BaudRate(mut input) => match byte {
+                b'0'..=b'9' => {
+                    input.push(byte as char);
+                    Some(self.baud_rate_prompt(input))
+                }
+                0x08 | 0x7f => {
+                    input.pop();
+                    Some(self.baud_rate_prompt(input))
+                }
+                b'\r' | b'\n' => match input.parse() {
+                    Ok(baud_rate) if baud_rate > 0 => {
+                        Some(Event::Command(Command::SetBaudRate(baud_rate)))
+                    }
+                    _ => Some(Event::Notice(format!("invalid baud rate: {}", input))),
+                },
+                0x1b => Some(Event::Notice("cancelled".to_owned())),
+                _ => {
+                    self.state = State::BaudRate(input);
+                    None
+                }
+            },
+        }
+    }
+
+    fn command(&mut self, byte: u8) -> Option<Event> {
+        if byte == self.escape {
+            return Some(Event::Send(byte));
+        }
+
+        // Accept both `q` and `Ctrl-Q` after the escape key
+        let key = if byte < 0x20 { byte + 0x60 } else { byte };
+        let command = match key.to_ascii_lowercase() {
+            b'q' | b'x' => Command::Quit,
+            b's' => Command::ShowSettings,
+            b'v' => Command::ToggleDisplay,
+            b'd' => Command::ToggleDtr,
+            b'r' => Command::ToggleRts,
+            b'e' => Command::Reset(None),
+            b'k' => Command::SendBreak,
+            b'i' => Command::Cycle(LineSetting::DataBits),
+            b'y' => Command::Cycle(LineSetting::Parity),
+            b'j' => Command::Cycle(LineSetting::StopBits),
+            b'f' => Command::Cycle(LineSetting::FlowControl),
+            b'b' => return Some(self.baud_rate_prompt(String::new())),
+            b'h' | b'?' => return Some(Event::Notice(self.help())),
+            _ => {
+                return Some(Event::Notice(format!(
+                    "unknown command: {} (type {} h for help)",
+                    key_name(byte),
+                    key_name(self.escape)
+                )))
+            }
+        };
+        Some(Event::Command(command))
+    }
+
+    fn baud_rate_prompt(&mut self, input: String) -> Event {
+        let prompt = format!("{}{}", BAUD_RATE_PROMPT, input);
+        self.state = State::BaudRate(input);
+        Event::Prompt(prompt)
+    }
+
+    /// Help message of the command keys.
+    pub fn help(&self) -> String {
+        let escape = key_name(self.escape);
+        format!(
+            "Commands (type {0} and then a key):\n\
+             \x20 {0}  send {0}\n\
+             \x20 q  quit\n\
+             \x20 s  show settings\n\
+             \x20 v  cycle output mode (visualized/raw/hex/auto)\n\
+             \x20 d  toggle DTR\n\
+             \x20 r  toggle RTS\n\
+             \x20 e  reset the board by DTR and RTS (--reset)\n\
+             \x20 k  send BREAK\n\
+             \x20 b  change baud rate\n\
+             \x20 i  change data bits\n\
+             \x20 y  change parity\n\
+             \x20 j  change stop bits\n\
+             \x20 f  change flow control\n\
+             \x20 h  show this help",
+            escape
+        )
+    }
+}
+
+/// Readable name of the key, such as `C-t`.
+pub fn key_name(byte: u8) -> String {
+    match byte {
+        0x00..=0x1f => format!("C-{}", (byte + 0x40).to_ascii_lowercase() as char),
+        0x7f => "DEL".to_owned(),
+        0x20..=0x7e => (byte as char).to_string(),
+        _ => format!("<{:02X}>", byte),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ESCAPE: u8 = 0x14;
+
+    #[test]
+    fn send() {
+        let mut menu = EscapeMenu::new(ESCAPE);
+        for &b in b"abc\x03\x1b\r\n" {
+            assert_eq!(menu.feed(b), Some(Event::Send(b)));
+        }
+
+        // Literal escape key
+        assert_eq!(menu.feed(ESCAPE), None);
+        assert_eq!(menu.feed(ESCAPE), Some(Event::Send(ESCAPE)));
+        assert_eq!(menu.feed(b'a'), Some(Event::Send(b'a')));
+    }
+
+    #[test]
+    fn commands() {
+        let mut menu = EscapeMenu::new(ESCAPE);
+        for &(key, ref command) in &[
+            (b'q', Command::Quit),
+            (b'x', Command::Quit),
+            (0x11, Command::Quit), // Ctrl-Q
+            (b'Q', Command::Quit),
+            (b's', Command::ShowSettings),
+            (b'v', Command::ToggleDisplay),
+            (b'd', Command::ToggleDtr),
+            (b'r', Command::ToggleRts),
+            (b'e', Command::Reset(None)),
+            (b'k', Command::SendBreak),
+            (b'i', Command::Cycle(LineSetting::DataBits)),
+            (0x09, Command::Cycle(LineSetting::DataBits)), // Ctrl-I
+            (b'y', Command::Cycle(LineSetting::Parity)),
+            (b'j', Command::Cycle(LineSetting::StopBits)),
+            (b'f', Command::Cycle(LineSetting::FlowControl)),
+        ] {
+            assert_eq!(menu.feed(ESCAPE), None);
+            assert_eq!(menu.feed(key), Some(Event::Command(command.clone())));
+            assert_eq!(menu.feed(b'a'), Some(Event::Send(b'a')));
+        }
+
+        assert_eq!(menu.feed(ESCAPE), None);
+        assert_eq!(menu.feed(b'h'), Some(Event::Notice(menu.help())));
+
+        assert_eq!(menu.feed(ESCAPE), None);
+        match menu.feed(b'z') {
+            Some(Event::Notice(_)) => {}
+            e => panic!("unexpected event: {:?}", e),
+        }
+        assert_eq!(menu.feed(b'z'), Some(Event::Send(b'z')));
+    }
+
+    #[test]
+    fn baud_rate() {
+        let mut menu = EscapeMenu::new(ESCAPE);
+        assert_eq!(menu.feed(ESCAPE), None);
+        assert_eq!(
+            menu.feed(b'b'),
+            Some(Event::Prompt("baud rate: ".to_owned()))
+        );
+        assert_eq!(
+            menu.feed(b'9'),
+            Some(Event::Prompt("baud rate: 9".to_owned()))
+        );
+        assert_eq!(menu.feed(b'x'), None);
+        assert_eq!(
+            menu.feed(b'7'),
+            Some(Event::Prompt("baud rate: 97".to_owned()))
+        );
+        assert_eq!(
+            menu.feed(0x7f),
+            Some(Event::Prompt("baud rate: 9".to_owned()))
+        );
+        for &b in b"21600" {
+            menu.feed(b);
+        }
+        assert_eq!(
+            menu.feed(b'\r'),
+            Some(Event::Command(Command::SetBaudRate(921_600)))
+        );
+        assert_eq!(menu.feed(b'\r'), Some(Event::Send(b'\r')));
+
+        // Empty
+        menu.feed(ESCAPE);
+        menu.feed(b'b');
+        match menu.feed(b'\r') {
+            Some(Event::Notice(_)) => {}
+            e => panic!("unexpected event: {:?}", e),
+        }
+
+        // Cancel
+        menu.feed(ESCAPE);
+        menu.feed(b'b');
+        menu.feed(b'1');
+        assert_eq!(menu.feed(0x1b), Some(Event::Notice("cancelled".to_owned())));
+        assert_eq!(menu.feed(b'1'), Some(Event::Send(b'1')));
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(key_name(0x14), "C-t");
+        assert_eq!(key_name(0x01), "C-a");
+        assert_eq!(key_name(0x1d), "C-]");
+        assert_eq!(key_name(b'a'), "a");
+        assert_eq!(key_name(0x7f), "DEL");
+        assert_eq!(key_name(0x80), "<80>");
+    }
+}
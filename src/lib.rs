@@ -1,5 +1,21 @@
+pub mod capture;
+pub mod config;
+pub mod control;
+pub mod device;
+pub mod encoding;
+pub mod error;
+pub mod escape;
+pub mod log;
 pub mod opt;
+pub mod pcap;
+pub mod port;
+pub mod render;
+pub mod script;
+pub mod term;
+pub mod timestamp;
+pub mod translate;
 pub mod util;
+pub mod watch;
 
 /// A "prelude" for crates using the [serialcat](index.html)
 pub mod prelude {
@@ -0,0 +1,213 @@
+//! Log files of session traffic
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// When to rotate log files.
+///
+/// The current file is renamed to `<path>.1`, and older files are renamed from `<path>.N` to
+/// `<path>.N+1`. Files beyond `keep` are removed.
+/// The default never rotates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotate before the file exceeds this size in bytes.
+    pub size: Option<u64>,
+    /// Rotate when this time has passed since the file was opened.
+    pub interval: Option<Duration>,
+    /// Number of rotated files to keep.
+    pub keep: usize,
+}
+
+/// Log file, flushed on every write.
+///
+/// ```no_run
+///     # use serialcat::log::{LogFile, Rotation};
+///     # use std::time::{Duration, Instant};
+///
+///     let rotation = Rotation {
+///         size: Some(10 << 20),
+///         interval: Some(Duration::from_secs(3600)),
+///         keep: 5,
+///     };
+///     let mut log = LogFile::open("serial.log", true, rotation, Instant::now()).unwrap();
+///     log.write(b"received bytes", Instant::now()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    rotation: Rotation,
+}
+
+impl LogFile {
+    /// Open the log file at `now`, appending to or truncating the existing file.
+    pub fn open<P>(path: P, append: bool, rotation: Rotation, now: Instant) -> io::Result<LogFile>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_owned();
+        let file = open(&path, append)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path,
+            file,
+            size,
+            opened: now,
+            rotation,
+        })
+    }
+
+    /// Write the bytes at `now`, rotating the file before if needed.
+    pub fn write(&mut self, bytes: &[u8], now: Instant) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        let too_large = self
+            .rotation
+            .size
+            .map(|size| self.size + bytes.len() as u64 > size)
+            .unwrap_or(false);
+        let too_old = self
+            .rotation
+            .interval
+            .map(|interval| now - self.opened >= interval)
+            .unwrap_or(false);
+        if self.size > 0 && (too_large || too_old) {
+            self.rotate(now)?;
+        }
+
+        self.file.write_all(bytes)?;
+        self.file.flush()?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: Instant) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            match fs::remove_file(numbered(self.rotation.keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..self.rotation.keep).rev() {
+                match fs::rename(numbered(n), numbered(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+
+        self.file = open(&self.path, false)?;
+        self.size = 0;
+        self.opened = now;
+        Ok(())
+    }
+}
+
+fn open(path: &Path, append: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("serialcat-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path, suffix: &str) -> String {
+        let mut path = path.to_owned().into_os_string();
+        path.push(suffix);
+        fs::read_to_string(path).unwrap_or_else(|_| "(none)".to_owned())
+    }
+
+    #[test]
+    fn append_truncate() {
+        let dir = temp_dir("append");
+        let path = dir.join("sc.log");
+        fs::write(&path, "old\n").unwrap();
+        let now = Instant::now();
+
+        let mut log = LogFile::open(&path, true, Rotation::default(), now).unwrap();
+        log.write(b"new\n", now).unwrap();
+        assert_eq!(read(&path, ""), "old\nnew\n");
+
+        let mut log = LogFile::open(&path, false, Rotation::default(), now).unwrap();
+        log.write(b"new\n", now).unwrap();
+        assert_eq!(read(&path, ""), "new\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_size() {
+        let dir = temp_dir("size");
+        let path = dir.join("sc.log");
+        let now = Instant::now();
+        let rotation = Rotation {
+            size: Some(4),
+            keep: 2,
+            ..Rotation::default()
+        };
+
+        let mut log = LogFile::open(&path, false, rotation, now).unwrap();
+        for bytes in &[&b"ab"[..], b"cd", b"ef", b"ghijkl", b"m"] {
+            log.write(bytes, now).unwrap();
+        }
+        // A write larger than the size is not split
+        assert_eq!(read(&path, ""), "m");
+        assert_eq!(read(&path, ".1"), "ghijkl");
+        assert_eq!(read(&path, ".2"), "ef");
+        assert_eq!(read(&path, ".3"), "(none)");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_interval() {
+        let dir = temp_dir("interval");
+        let path = dir.join("sc.log");
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let rotation = Rotation {
+            interval: Some(Duration::from_secs(60)),
+            keep: 0,
+            ..Rotation::default()
+        };
+
+        let mut log = LogFile::open(&path, false, rotation, start).unwrap();
+        log.write(b"a", at(0)).unwrap();
+        log.write(b"b", at(59)).unwrap();
+        log.write(b"c", at(60)).unwrap();
+        log.write(b"d", at(100)).unwrap();
+        log.write(b"e", at(120)).unwrap();
+        assert_eq!(read(&path, ""), "e");
+        assert_eq!(read(&path, ".1"), "(none)");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,109 +1,1281 @@
 use anyhow::{bail, Context as _, Result};
-use bytes::{Buf, BufMut, BytesMut};
-use futures::prelude::*;
-use std::time::Duration;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::{DateTime, FixedOffset};
+use futures::{future::FusedFuture as _, prelude::*};
+use std::{
+    fs::File,
+    os::unix::io::AsRawFd as _,
+    path::Path,
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::{AsyncRead, AsyncWrite, BufWriter},
+    io::{AsyncRead, AsyncWrite, BufReader, BufWriter, ReadHalf, WriteHalf},
+    net::{UnixListener, UnixStream},
     prelude::*,
+    signal::unix::{signal, SignalKind},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
     time::timeout,
 };
-use tokio_serial::{Serial, SerialPortSettings};
+use tokio_serial::Serial;
+
+use serialcat::{
+    capture::{CaptureReader, CaptureWriter, Direction, Header},
+    control::{self, Command, LineSetting},
+    device::{self, Selector},
+    encoding::Encoder,
+    error::{self, Error},
+    escape::{self, EscapeMenu, Event},
+    log::{LogFile, Rotation},
+    opt::{self, Opt},
+    pcap::PcapWriter,
+    port,
+    render::{self, OutputMode},
+    script::{self, Matcher, Script, Statement},
+    term,
+    timestamp::{HeldTimes, ReadTime, Timestamper},
+    translate::{InputMap, InputTranslator, OutputMap, OutputTranslator},
+    watch::LineWatcher,
+};
 
-use serialcat::{opt, prelude::*, util::GetChars};
+/// Interval to look for the serial port to come back with `--reconnect`.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+/// Interval to look for the serial port to appear with `--wait`.
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Event to the reader, which owns stdout.
+enum Display {
+    /// Show the message.
+    Notice(String),
+    /// Show the prompt line.
+    Prompt(String),
+    /// Switch the output mode.
+    SetMode(OutputMode),
+    /// Show the message, and discard bytes received with the old line settings.
+    Reconfigured(String),
+}
 
 #[tokio::main]
 async fn main() {
-    sc_main().await.unwrap_or_else(|e| {
-        eprintln!("{:#}", e);
+    let status = sc_main().await.unwrap_or_else(|e| {
+        // Quietly exit as other commands do when the reader of the pipe is gone
+        if e.downcast_ref() != Some(&Error::StdoutClosed) {
+            eprintln!("{:#}", e);
+        }
+        error::status(&e)
     });
 
     // Force stopping reading stdin
-    std::process::exit(0);
+    std::process::exit(status);
 }
 
-async fn sc_main() -> Result<()> {
+/// Run `sc` and return the exit status.
+async fn sc_main() -> Result<i32> {
     let opt = opt::parse_args();
+    if opt.list {
+        let ports = device::list();
+        let list = if opt.json {
+            device::format_json(&ports)
+        } else {
+            device::format_table(&ports)
+        };
+        std::io::Write::write_all(&mut std::io::stdout(), list.as_bytes())
+            .map_err(|e| stdout_error(e, "Cannot write to stdout"))?;
+        return Ok(0);
+    }
+    if let Some(opt::Subcommand::Replay {
+        file,
+        realtime,
+        sent,
+    }) = &opt.command
+    {
+        replay(&opt, file, *realtime, *sent).await?;
+        return Ok(0);
+    }
+    let selector = opt.port.as_deref().context("No serial port given")?;
 
-    let settings = SerialPortSettings {
+    let script = match &opt.script {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Cannot read script file: {}", path))?;
+            let script =
+                Script::parse(&text).with_context(|| format!("Invalid script file: {}", path))?;
+            Some(script)
+        }
+        None => None,
+    };
+
+    let settings = port::Settings {
         baud_rate: opt.baud_rate,
         data_bits: opt.data_bits,
-        flow_control: opt.flow_control,
         parity: opt.parity,
         stop_bits: opt.stop_bits,
-        timeout: Duration::from_millis(50),
+        flow_control: opt.flow_control,
+    };
+    let lines = port::ModemLines {
+        dtr: opt.dtr,
+        rts: opt.rts,
+        no_hupcl: opt.no_hupcl,
+    };
+    let (port, serial) = match &opt.wait {
+        Some(timeout) => wait_port(selector, &settings, &lines, timeout.first().copied()).await?,
+        None => {
+            let port =
+                device::resolve(selector).with_context(|| Error::OpenPort(selector.to_owned()))?;
+            let serial = port::open(&port, &settings, &lines).map_err(|e| {
+                let context = Error::open_port(&port, &e);
+                e.context(context)
+            })?;
+            (port, serial)
+        }
     };
-    let serial = Serial::from_path(&opt.port, &settings)
-        .with_context(|| format!("Cannot open serial port: {}", opt.port))?;
+    let port = port.as_str();
+    let control = port::Control::new(&serial)?;
     let (serial_rx, serial_tx) = tokio::io::split(serial);
 
-    let reader = {
-        let raw = opt.raw;
-        async move {
-            serial_reader(serial_rx, tokio::io::stdout(), raw)
-                .await
-                .context("An error occurred on reader")
+    let (display_tx, display_rx) = unbounded_channel();
+    if let Some(notice) = rounded_baud_rate(opt.baud_rate, &control)? {
+        let _ = display_tx.send(Display::Notice(notice));
+    }
+    if opt.no_hupcl {
+        let notice = format!(
+            "HUPCL cleared on {}: DTR and RTS stay on after exit until `stty -F {} hupcl`",
+            port, port
+        );
+        let _ = display_tx.send(Display::Notice(notice));
+    }
+    let (request_tx, request_rx) = unbounded_channel();
+
+    let (writer_tx, writer_rx) = unbounded_channel();
+    let (control_tx, control_rx) = unbounded_channel();
+    let (reconnect, replacements) = if opt.reconnect {
+        let reconnect = Reconnect {
+            port: port.to_owned(),
+            serial_number: device::usb_serial_number(port),
+            settings,
+            lines,
+            writers: writer_tx,
+            controls: control_tx,
+            display: display_tx.clone(),
+        };
+        (Some(reconnect), Some(writer_rx))
+    } else {
+        (None, None)
+    };
+    let serial_rx = SerialRx {
+        port: Some(serial_rx),
+        reconnect,
+    };
+    let serial_tx = SerialTx {
+        port: Some(serial_tx),
+        replacements,
+    };
+
+    let (socket, _socket_file) = match &opt.control {
+        Some(path) => {
+            let listener = bind_control(path)
+                .with_context(|| format!("Cannot bind control socket: {}", path))?;
+            (Some(listener), Some(SocketFile(path.clone())))
         }
-        .fuse()
+        None => (None, None),
     };
-    let writer = {
-        let escape_quit = opt.escape_quit;
-        async move {
-            serial_writer(tokio::io::stdin(), serial_tx, escape_quit)
+
+    let log = match &opt.log {
+        Some(path) => {
+            let rotation = Rotation {
+                size: opt.log_rotate_size,
+                interval: opt.log_rotate_interval,
+                keep: opt.log_keep,
+            };
+            let log = LogFile::open(path, opt.log_append, rotation, Instant::now())
+                .with_context(|| format!("Cannot open log file: {}", path))?;
+            Some(log)
+        }
+        None => None,
+    };
+    let capture = match &opt.capture {
+        Some(path) => {
+            let header = Header {
+                start: chrono::Local::now().into(),
+                settings: vec![
+                    ("port".to_owned(), port.to_owned()),
+                    ("line".to_owned(), port::describe(&control.settings()?)),
+                    ("encoding".to_owned(), opt.encoding.to_string()),
+                ],
+            };
+            let capture = File::create(path)
+                .and_then(|file| CaptureWriter::new(file, &header, Instant::now()))
+                .with_context(|| format!("Cannot create capture file: {}", path))?;
+            Some(capture)
+        }
+        None => None,
+    };
+    let pcap = match &opt.pcap {
+        Some(path) => {
+            let split_idle = opt.pcap_split_idle.map(Duration::from_millis);
+            let pcap = File::create(path)
+                .and_then(|file| {
+                    PcapWriter::new(file, opt.pcap_link_type, port, ReadTime::now(), split_idle)
+                })
+                .with_context(|| format!("Cannot create pcapng file: {}", path))?;
+            Some(pcap)
+        }
+        None => None,
+    };
+    let recorder = Recorder {
+        log,
+        log_sent: opt.log_tx,
+        capture,
+        pcap,
+    };
+    let (traffic_tx, traffic_rx) = unbounded_channel();
+    let traffic_tx = if recorder.is_enabled() {
+        Some(traffic_tx)
+    } else {
+        None
+    };
+    let (script_tx, script_rx) = unbounded_channel();
+    let watcher = LineWatcher::new(
+        opt.fail_on.iter().chain(&opt.exit_on).cloned().collect(),
+        opt.encoding,
+        opt.imap,
+    );
+    let (watch_tx, watch_rx) = unbounded_channel();
+    let mut taps = Vec::new();
+    taps.extend(traffic_tx.clone());
+    if script.is_some() {
+        taps.push(script_tx);
+    }
+    if !watcher.is_empty() {
+        taps.push(watch_tx);
+    }
+
+    let recording = recorder.is_enabled();
+    let recorder = async move {
+        if !recorder.is_enabled() {
+            return future::pending().await;
+        }
+        record(recorder, traffic_rx).await
+    }
+    .fuse();
+    futures::pin_mut!(recorder);
+
+    // The reader and the writer are dropped at the end of the block, closing the traffic channel
+    let result = {
+        let stdin = tokio::io::stdin();
+        let raw_mode = if script.is_some() {
+            None
+        } else {
+            term::RawMode::enable(std::io::stdin().as_raw_fd())
+                .context("Cannot set terminal mode")?
+        };
+        let interactive = raw_mode.as_ref().map(|raw_mode| {
+            let _ = display_tx.send(Display::Notice(format!(
+                "Connected to {} (type {} h for help)",
+                port,
+                escape::key_name(opt.escape_char)
+            )));
+            Interactive {
+                menu: EscapeMenu::new(opt.escape_char),
+                eof: if opt.escape_quit {
+                    Some(raw_mode.eof_char())
+                } else {
+                    None
+                },
+                requests: request_tx.clone(),
+                display: display_tx.clone(),
+            }
+        });
+
+        let reader = {
+            let config = render_config(&opt);
+            let imap = opt.imap;
+            let timestamper = opt
+                .timestamp
+                .map(|mode| Timestamper::new(mode, Instant::now()));
+            async move {
+                serial_reader(
+                    serial_rx,
+                    tokio::io::stdout(),
+                    config,
+                    imap,
+                    timestamper,
+                    taps,
+                    display_rx,
+                )
                 .await
-                .context("An error occurred on writer")
+                .context("An error occurred on reader")
+            }
+            .fuse()
+        };
+        let writer = {
+            let escape_quit = opt.escape_quit;
+            let encoder = Encoder::new(opt.encoding);
+            let omap = opt.omap;
+            let requests = request_tx.clone();
+            async move {
+                match script {
+                    Some(script) => run_script(script, serial_tx, script_rx, requests, traffic_tx)
+                        .await
+                        .context("An error occurred on script"),
+                    None => serial_writer(
+                        stdin,
+                        serial_tx,
+                        escape_quit,
+                        encoder,
+                        omap,
+                        interactive,
+                        traffic_tx,
+                    )
+                    .await
+                    .map(|()| 0)
+                    .context("An error occurred on writer"),
+                }
+            }
+            .fuse()
+        };
+        let controller = {
+            let port = port.to_owned();
+            let output = opt.output;
+            let reset = opt.reset;
+            async move {
+                controller(
+                    control, port, output, reset, request_rx, control_rx, display_tx,
+                )
+                .await
+                .context("An error occurred on controller")
+            }
+            .fuse()
+        };
+        let socket = async move {
+            match socket {
+                Some(listener) => control_socket(listener, request_tx).await,
+                None => future::pending().await,
+            }
         }
-        .fuse()
+        .fuse();
+        let watcher = async move {
+            if watcher.is_empty() {
+                return future::pending().await;
+            }
+            watch(watcher, watch_rx).await
+        }
+        .fuse();
+        let deadline = {
+            let duration = opt.timeout;
+            async move {
+                match duration {
+                    Some(duration) => {
+                        tokio::time::delay_for(duration).await;
+                        Err(Error::Timeout(format!("Timed out after {:?}", duration)))
+                    }
+                    None => future::pending().await,
+                }
+            }
+            .fuse()
+        };
+        let signals = wait_signals().fuse();
+        futures::pin_mut!(reader, writer, controller, socket, watcher, deadline, signals);
+
+        futures::select! {
+            result = &mut reader => result.map(|()| 0),
+            result = &mut writer => result,
+            status = &mut watcher => Ok(status),
+            result = &mut deadline => result.map_err(Into::into),
+            result = &mut controller => result.map(|()| 0),
+            result = &mut socket => result.map(|()| 0),
+            result = &mut recorder => result.map(|()| 0),
+            result = &mut signals => result,
+        }
+    };
+
+    // Write traffic left in the channel even on errors, and then report the errors
+    let recorded = if recording && !recorder.is_terminated() {
+        recorder.await
+    } else {
+        Ok(())
     };
-    futures::pin_mut!(reader, writer);
+    let status = result?;
+    recorded?;
+    Ok(status)
+}
+
+/// Wait for the serial port selected to exist and be accessible, and open it.
+///
+/// Return the path of the port with the handle.
+async fn wait_port(
+    selector: &str,
+    settings: &port::Settings,
+    lines: &port::ModemLines,
+    timeout: Option<Duration>,
+) -> Result<(String, Serial)> {
+    let open_port = || Error::OpenPort(selector.to_owned());
+    let parsed = selector.parse::<Selector>().with_context(open_port)?;
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        let result = parsed
+            .find_one()
+            .with_context(open_port)?
+            .map(|port| (port::open(&port, settings, lines), port));
+        let expired = timeout.filter(|&timeout| start.elapsed() >= timeout);
+        match (result, expired) {
+            (Some((Ok(serial), port)), _) => return Ok((port, serial)),
+            (Some((Err(e), port)), Some(_)) => {
+                let context = Error::open_port(&port, &e);
+                return Err(e.context(context));
+            }
+            (None, Some(timeout)) => {
+                let msg = format!("Serial port did not appear in {:?}: {}", timeout, selector);
+                return Err(Error::Timeout(msg).into());
+            }
+            _ => {}
+        }
+
+        if !waiting {
+            eprintln!("Waiting for {}", selector);
+            waiting = true;
+        }
+        tokio::time::delay_for(WAIT_INTERVAL).await;
+    }
+}
+
+/// Tell when the driver rounded the baud rate to another it can generate.
+fn rounded_baud_rate(baud_rate: u32, control: &port::Control) -> Result<Option<String>> {
+    let actual = control.settings()?.baud_rate;
+    if actual == baud_rate {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "Baud rate {} is not supported, the driver uses {}",
+        baud_rate, actual
+    )))
+}
+
+/// Watch received lines, and return the exit status of the first line matching a pattern.
+async fn watch(mut watcher: LineWatcher, mut received: UnboundedReceiver<Traffic>) -> i32 {
+    while let Some(traffic) = received.next().await {
+        if let Some(status) = watcher.feed(&traffic.bytes) {
+            return status;
+        }
+    }
+    future::pending().await
+}
+
+/// Configuration of the display from the options.
+fn render_config(opt: &Opt) -> render::Config {
+    render::Config {
+        mode: opt.output,
+        style: render::Style {
+            ctrl: opt.ctrl_style,
+            color: !opt.no_color,
+            pass: opt.pass_through.clone(),
+            ansi: opt.ansi,
+        },
+        encoding: opt.encoding,
+        idle: Duration::from_millis(opt.idle),
+        binary_threshold: opt.binary_threshold,
+    }
+}
+
+/// Bytes received from or sent to the serial port.
+#[derive(Clone)]
+struct Traffic {
+    direction: Direction,
+    time: Instant,
+    bytes: Bytes,
+}
+
+/// Files to record traffic.
+struct Recorder {
+    log: Option<LogFile>,
+    /// Also write sent bytes to the log file.
+    log_sent: bool,
+    capture: Option<CaptureWriter<File>>,
+    pcap: Option<PcapWriter<File>>,
+}
+
+impl Recorder {
+    fn is_enabled(&self) -> bool {
+        self.log.is_some() || self.capture.is_some() || self.pcap.is_some()
+    }
+
+    fn write(&mut self, traffic: Traffic) -> Result<()> {
+        let Traffic {
+            direction,
+            time,
+            bytes,
+        } = traffic;
+        if let Some(log) = &mut self.log {
+            if direction == Direction::Received || self.log_sent {
+                log.write(&bytes, time).context("Cannot write log file")?;
+            }
+        }
+        if let Some(capture) = &mut self.capture {
+            capture
+                .write(direction, &bytes, time)
+                .context("Cannot write capture file")?;
+        }
+        if let Some(pcap) = &mut self.pcap {
+            pcap.write(direction, &bytes, time)
+                .context("Cannot write pcapng file")?;
+        }
+        Ok(())
+    }
+
+    /// Time to wait before flushing held packets.
+    fn idle(&self) -> Option<Duration> {
+        self.pcap
+            .as_ref()
+            .filter(|pcap| pcap.pending())
+            .and_then(|pcap| pcap.split_idle())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(pcap) = &mut self.pcap {
+            pcap.flush().context("Cannot write pcapng file")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write traffic from the reader and the writer to the files.
+async fn record(mut recorder: Recorder, mut traffic: UnboundedReceiver<Traffic>) -> Result<()> {
+    loop {
+        let next = match recorder.idle() {
+            Some(idle) => match timeout(idle, traffic.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    recorder.flush()?;
+                    continue;
+                }
+            },
+            None => traffic.next().await,
+        };
+        match next {
+            Some(next) => recorder.write(next)?,
+            None => return recorder.flush(),
+        }
+    }
+}
+
+/// Display a capture file as the session displayed received bytes.
+async fn replay(opt: &Opt, path: &str, realtime: bool, sent: bool) -> Result<()> {
+    let capture = File::open(path)
+        .and_then(|file| CaptureReader::new(std::io::BufReader::new(file)))
+        .with_context(|| format!("Cannot open capture file: {}", path))?;
+    let header = capture.header().clone();
+    let start = Instant::now();
+
+    let mut msg = format!(
+        "Replaying {} captured at {}",
+        path,
+        header.start.to_rfc3339()
+    );
+    for (name, value) in &header.settings {
+        msg.push_str(&format!("\n{}: {}", name, value));
+    }
+    let mut stdout = tokio::io::stdout();
+    write_display(&mut stdout, Display::Notice(msg), &mut false).await?;
+
+    let wall = header.start;
+    let chunks = stream::unfold(capture, move |mut capture| async move {
+        let result = loop {
+            let record = match capture.read_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => return None,
+                Err(e) => break Err(anyhow::Error::new(e).context("Cannot read capture file")),
+            };
+            if record.direction == Direction::Sent && !sent {
+                continue;
+            }
+
+            let time = match replay_time(start, wall, record.time) {
+                Some(time) => time,
+                None => break Err(anyhow::anyhow!("Invalid time in capture file")),
+            };
+            if realtime {
+                tokio::time::delay_until(time.instant.into()).await;
+            }
+            break Ok((time, Bytes::from(record.bytes)));
+        };
+        Some((result, capture))
+    });
+
+    let config = render_config(opt);
+    let timestamper = opt.timestamp.map(|mode| Timestamper::new(mode, start));
+    let (_, events) = unbounded_channel();
+    display(
+        chunks,
+        stdout,
+        config,
+        opt.imap,
+        timestamper,
+        Vec::new(),
+        events,
+    )
+    .await
+}
+
+/// Time when recorded bytes are replayed.
+fn replay_time(start: Instant, wall: DateTime<FixedOffset>, time: Duration) -> Option<ReadTime> {
+    Some(ReadTime {
+        instant: start.checked_add(time)?,
+        wall: wall.checked_add_signed(chrono::Duration::from_std(time).ok()?)?,
+    })
+}
+
+/// Wait for signals to terminate, so that the terminal settings are restored before exiting.
+///
+/// Return the exit status as a shell reports the process killed by the signal.
+async fn wait_signals() -> Result<i32> {
+    let mut streams = Vec::new();
+    for &signo in &[libc::SIGHUP, libc::SIGINT, libc::SIGQUIT, libc::SIGTERM] {
+        let stream = signal(SignalKind::from_raw(signo)).context("Cannot handle signals")?;
+        streams.push(stream.map(move |()| error::EXIT_SIGNAL + signo));
+    }
+
+    let status = futures::stream::select_all(streams).next().await;
+    Ok(status.unwrap_or(error::EXIT_SIGNAL))
+}
+
+/// Request to the controller.
+struct Request {
+    command: Command,
+    /// Channel to send back the result, if the requester waits for it.
+    reply: Option<oneshot::Sender<Result<String, String>>>,
+}
+
+impl Request {
+    fn new(command: Command) -> Request {
+        Request {
+            command,
+            reply: None,
+        }
+    }
+}
+
+/// Execute commands from the escape menu and the control socket.
+///
+/// With `--reconnect`, the control is closed (`None`) and replaced by the reader.
+async fn controller(
+    mut control: port::Control,
+    port: String,
+    mut output: OutputMode,
+    reset: port::ResetPulse,
+    requests: UnboundedReceiver<Request>,
+    controls: UnboundedReceiver<Option<port::Control>>,
+    display: UnboundedSender<Display>,
+) -> Result<()> {
+    fn on_off(level: bool) -> &'static str {
+        if level {
+            "on"
+        } else {
+            "off"
+        }
+    }
+    fn line(control: &port::Control) -> Result<String> {
+        Ok(format!("line: {}", port::describe(&control.settings()?)))
+    }
+
+    let mut requests = requests.fuse();
+    let mut controls = controls.fuse();
+    loop {
+        let Request { command, reply } = futures::select! {
+            request = requests.next() => match request {
+                Some(request) => request,
+                None => return Ok(()),
+            },
+            replaced = controls.next() => {
+                match replaced {
+                    Some(Some(replaced)) => control = replaced,
+                    Some(None) => control.close(),
+                    None => {}
+                }
+                continue;
+            }
+        };
+
+        // Bytes in flight were received with the old settings, if line settings change
+        let mut reconfigured = false;
+        let result = match command {
+            Command::Quit => {
+                if let Some(reply) = reply {
+                    let _ = reply.send(Ok("quit".to_owned()));
+                }
+                return Ok(());
+            }
+            Command::ShowSettings => (|| {
+                Ok(format!(
+                    "port: {}\n{}\nDTR: {}\nRTS: {}\ndisplay: {}",
+                    port,
+                    line(&control)?,
+                    control.dtr().map_or("unknown", on_off),
+                    control.rts().map_or("unknown", on_off),
+                    output,
+                ))
+            })(),
+            Command::ToggleDisplay => {
+                output = output.next();
+                let _ = display.send(Display::SetMode(output));
+                Ok(format!("display: {}", output))
+            }
+            Command::ToggleDtr => control.dtr().and_then(|level| {
+                control.set_dtr(!level)?;
+                Ok(format!("DTR: {}", on_off(!level)))
+            }),
+            Command::ToggleRts => control.rts().and_then(|level| {
+                control.set_rts(!level)?;
+                Ok(format!("RTS: {}", on_off(!level)))
+            }),
+            Command::SetDtr(level) => control
+                .set_dtr(level)
+                .map(|()| format!("DTR: {}", on_off(level))),
+            Command::SetRts(level) => control
+                .set_rts(level)
+                .map(|()| format!("RTS: {}", on_off(level))),
+            Command::Reset(pulse) => {
+                let pulse = pulse.unwrap_or(reset);
+                tokio::task::block_in_place(|| control.reset(pulse))
+                    .map(|()| format!("reset: {}", pulse))
+            }
+            Command::SendBreak => tokio::task::block_in_place(|| control.send_break())
+                .map(|()| "BREAK sent".to_owned()),
+            Command::SetBaudRate(baud_rate) => {
+                reconfigured = true;
+                (|| {
+                    control.set_baud_rate(baud_rate)?;
+                    let line = line(&control)?;
+                    Ok(match rounded_baud_rate(baud_rate, &control)? {
+                        Some(notice) => format!("{}\n{}", line, notice),
+                        None => line,
+                    })
+                })()
+            }
+            Command::SetDataBits(data_bits) => {
+                reconfigured = true;
+                control
+                    .set_data_bits(data_bits)
+                    .and_then(|()| line(&control))
+            }
+            Command::SetParity(parity) => {
+                reconfigured = true;
+                control.set_parity(parity).and_then(|()| line(&control))
+            }
+            Command::SetStopBits(stop_bits) => {
+                reconfigured = true;
+                control
+                    .set_stop_bits(stop_bits)
+                    .and_then(|()| line(&control))
+            }
+            Command::SetFlowControl(flow_control) => control
+                .set_flow_control(flow_control)
+                .and_then(|()| line(&control)),
+            Command::Cycle(setting) => (|| {
+                reconfigured = setting != LineSetting::FlowControl;
+                let settings = control.settings()?;
+                match setting {
+                    LineSetting::DataBits => {
+                        control.set_data_bits(control::next_data_bits(settings.data_bits))?
+                    }
+                    LineSetting::Parity => {
+                        control.set_parity(control::next_parity(settings.parity))?
+                    }
+                    LineSetting::StopBits => {
+                        control.set_stop_bits(control::next_stop_bits(settings.stop_bits))?
+                    }
+                    LineSetting::FlowControl => control
+                        .set_flow_control(control::next_flow_control(settings.flow_control))?,
+                }
+                line(&control)
+            })(),
+        };
+
+        let result = result.map_err(|e| format!("{:#}", e));
+        let notice = match &result {
+            Ok(msg) | Err(msg) => msg.clone(),
+        };
+        let _ = if reconfigured && result.is_ok() {
+            display.send(Display::Reconfigured(notice))
+        } else {
+            display.send(Display::Notice(notice))
+        };
+        if let Some(reply) = reply {
+            let _ = reply.send(result);
+        }
+    }
+}
+
+/// Bind the control socket, replacing a stale socket file left by a crashed session.
+fn bind_control(path: &str) -> std::io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            // Another session is still listening if it accepts connections
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
+/// Accept clients of the control socket.
+async fn control_socket(
+    mut listener: UnixListener,
+    requests: UnboundedSender<Request>,
+) -> Result<()> {
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Cannot accept control socket")?;
+        let requests = requests.clone();
+        tokio::spawn(async move {
+            let _ = control_client(stream, requests).await;
+        });
+    }
+}
+
+/// Serve a client of the control socket.
+///
+/// The client sends a command per line, and receives `ok <message>` or `error <message>` per line.
+async fn control_client(stream: UnixStream, requests: UnboundedSender<Request>) -> Result<()> {
+    let (rx, mut tx) = tokio::io::split(stream);
+    let mut lines = BufReader::new(rx).lines();
 
-    futures::select! {
-        result = &mut reader => result?,
-        result = &mut writer => result?,
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match control::parse(&line) {
+            Ok(command) => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                let request = Request {
+                    command,
+                    reply: Some(reply_tx),
+                };
+                if requests.send(request).is_err() {
+                    break;
+                }
+                reply_rx
+                    .await
+                    .unwrap_or_else(|_| Err("session closed".to_owned()))
+            }
+            Err(e) => Err(format!("{:#}", e)),
+        };
+        let reply = match result {
+            Ok(msg) => format!("ok {}\n", msg.replace('\n', "; ")),
+            Err(msg) => format!("error {}\n", msg.replace('\n', "; ")),
+        };
+        tx.write_all(reply.as_bytes()).await?;
     }
 
     Ok(())
 }
 
-async fn serial_reader<R, W>(mut serial_rx: R, stdout: W, raw: bool) -> Result<()>
+/// Remove the socket file when the session ends.
+struct SocketFile(String);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Read half of the serial port, reopened when it comes back with `--reconnect`.
+struct SerialRx {
+    /// `None` while disconnected.
+    port: Option<ReadHalf<Serial>>,
+    reconnect: Option<Reconnect>,
+}
+
+impl SerialRx {
+    /// Read bytes into the buffer, waiting for the port to come back if reconnecting.
+    async fn read(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        loop {
+            let e = match &mut self.port {
+                Some(port) => match port.read_buf(buffer).await {
+                    // EOF of a serial port means that it is hung up
+                    Ok(0) => Error::Disconnected.into(),
+                    Ok(_) => return Ok(()),
+                    Err(e) => serial_error(e, "Cannot read serial port"),
+                },
+                None => Error::Disconnected.into(),
+            };
+            match &self.reconnect {
+                Some(reconnect) if is_disconnected(&e) => {
+                    self.port = None;
+                    self.port = Some(reconnect.reconnect().await?);
+                }
+                _ => return Err(e),
+            }
+        }
+    }
+}
+
+/// Write half of the serial port, replaced by the reader with `--reconnect`.
+struct SerialTx<W> {
+    /// `None` while disconnected.
+    port: Option<W>,
+    /// Replacements of the port, `None` to close it.
+    replacements: Option<UnboundedReceiver<Option<W>>>,
+}
+
+impl<W> SerialTx<W>
 where
-    R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let mut buffer = BytesMut::with_capacity(1024);
-    let mut stdout = BufWriter::new(stdout);
+    /// Take the latest replacement of the port, if any.
+    fn update(&mut self) {
+        if let Some(replacements) = &mut self.replacements {
+            while let Ok(port) = replacements.try_recv() {
+                self.port = port;
+            }
+        }
+    }
 
-    let mut reversed = false;
+    /// Wait for a replacement of the port.
+    async fn replaced(&mut self) {
+        let replacement = match &mut self.replacements {
+            Some(replacements) => replacements.next().await,
+            None => None,
+        };
+        match replacement {
+            Some(port) => self.port = port,
+            None => {
+                self.replacements = None;
+                future::pending().await
+            }
+        }
+    }
 
-    let drop_bufferd = timeout(Duration::from_millis(100), async {
+    /// Run the future, taking replacements of the port meanwhile not to hold a closed one.
+    async fn updating<F>(&mut self, future: F) -> F::Output
+    where
+        F: Future,
+    {
+        let future = future.fuse();
+        futures::pin_mut!(future);
         loop {
-            let result = serial_rx
-                .read_buf(&mut buffer)
-                .await
-                .context("Cannot read serial port");
-            buffer.clear();
+            let replaced = self.replaced().fuse();
+            futures::pin_mut!(replaced);
+            futures::select! {
+                output = &mut future => return output,
+                () = replaced => {}
+            }
+        }
+    }
 
-            if let Err(e) = result {
+    /// Write all bytes and flush them.
+    ///
+    /// While disconnected, the bytes are dropped, or wait for the port to come back if `wait`.
+    async fn write(&mut self, bytes: &[u8], wait: bool) -> Result<()> {
+        loop {
+            self.update();
+            let port = match &mut self.port {
+                Some(port) => port,
+                None if wait && self.replacements.is_some() => {
+                    self.replaced().await;
+                    continue;
+                }
+                None => return Ok(()),
+            };
+            let result = match port.write_all(bytes).await {
+                Ok(()) => port
+                    .flush()
+                    .await
+                    .map_err(|e| serial_error(e, "Cannot flush serial port")),
+                Err(e) => Err(serial_error(e, "Cannot write serial port")),
+            };
+            match result {
+                Err(e) if self.replacements.is_some() && is_disconnected(&e) => {
+                    self.port = None;
+                    if !wait {
+                        return Ok(());
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// How to reopen the serial port when it disappears, with `--reconnect`.
+struct Reconnect {
+    port: String,
+    /// USB serial number of the port, to find it even if it comes back with another path.
+    serial_number: Option<String>,
+    settings: port::Settings,
+    lines: port::ModemLines,
+    writers: UnboundedSender<Option<WriteHalf<Serial>>>,
+    controls: UnboundedSender<Option<port::Control>>,
+    display: UnboundedSender<Display>,
+}
+
+impl Reconnect {
+    /// Wait for the port to come back and reopen it, passing the other handles to the writer and
+    /// the controller.
+    async fn reconnect(&self) -> Result<ReadHalf<Serial>> {
+        // Close the port everywhere, or the device may come back with another path
+        let _ = self.writers.send(None);
+        let _ = self.controls.send(None);
+        let _ = self.display.send(Display::Notice(format!(
+            "Disconnected from {}, waiting for it to come back",
+            self.port
+        )));
+
+        let (serial, path) = loop {
+            tokio::time::delay_for(RECONNECT_INTERVAL).await;
+            let path = if Path::new(&self.port).exists() {
+                Some(self.port.clone())
+            } else {
+                self.serial_number
+                    .as_deref()
+                    .and_then(device::find_by_usb_serial_number)
+            };
+            // The device may not be ready yet even if it exists
+            if let Some(path) = path {
+                if let Ok(serial) = port::open(&path, &self.settings, &self.lines) {
+                    break (serial, path);
+                }
+            }
+        };
+
+        let control = port::Control::new(&serial)?;
+        let (serial_rx, serial_tx) = tokio::io::split(serial);
+        let _ = self.controls.send(Some(control));
+        let _ = self.writers.send(Some(serial_tx));
+        // The port comes back with the settings at start, so bytes held for the old ones are stale
+        let _ = self.display.send(Display::Reconfigured(format!(
+            "Reconnected to {} with the initial line settings, dropping changes made since",
+            path
+        )));
+        Ok(serial_rx)
+    }
+}
+
+/// Whether the error is that the serial port disappeared.
+fn is_disconnected(e: &anyhow::Error) -> bool {
+    e.downcast_ref() == Some(&Error::Disconnected)
+}
+
+async fn serial_reader<W>(
+    mut serial_rx: SerialRx,
+    stdout: W,
+    config: render::Config,
+    imap: InputMap,
+    timestamper: Option<Timestamper>,
+    taps: Vec<UnboundedSender<Traffic>>,
+    events: UnboundedReceiver<Display>,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = BytesMut::with_capacity(1024);
+
+    let drop_bufferd = timeout(Duration::from_millis(100), async {
+        loop {
+            if let Err(e) = serial_rx.read(&mut buffer).await {
                 break e;
             }
+            buffer.clear();
         }
     });
     if let Ok(e) = drop_bufferd.await {
         return Err(e);
     }
 
+    let chunks = stream::unfold(serial_rx, |mut serial_rx| async move {
+        let mut buffer = BytesMut::with_capacity(1024);
+        let result = serial_rx
+            .read(&mut buffer)
+            .await
+            .map(|()| (ReadTime::now(), buffer.freeze()));
+        Some((result, serial_rx))
+    });
+    display(chunks, stdout, config, imap, timestamper, taps, events).await
+}
+
+/// Display chunks of received bytes and events until the chunks end.
+///
+/// Chunks are sent to the taps before they are displayed.
+async fn display<S, W>(
+    chunks: S,
+    stdout: W,
+    mut config: render::Config,
+    imap: InputMap,
+    mut timestamper: Option<Timestamper>,
+    taps: Vec<UnboundedSender<Traffic>>,
+    events: UnboundedReceiver<Display>,
+) -> Result<()>
+where
+    S: Stream<Item = Result<(ReadTime, Bytes)>>,
+    W: AsyncWrite + Unpin,
+{
+    enum Received {
+        Bytes(ReadTime, Bytes),
+        Idle,
+        Event(Option<Display>),
+        End,
+    }
+
+    let mut translated = BytesMut::with_capacity(1024);
+    let mut rendered = BytesMut::with_capacity(1024);
+    let mut buffer = BytesMut::with_capacity(1024);
+    let mut stdout = BufWriter::new(stdout);
+    let mut translator = InputTranslator::new(imap);
+    let mut renderer = config.renderer();
+    let chunks = chunks.fuse();
+    futures::pin_mut!(chunks);
+    let mut events = events.fuse();
+
+    let mut prompting = false;
+    let mut held = HeldTimes::default();
+
     loop {
-        serial_rx
-            .read_buf(&mut buffer)
+        let pending = renderer.pending() || translator.pending();
+        let idle_timer = async {
+            if pending {
+                tokio::time::delay_for(config.idle).await
+            } else {
+                future::pending().await
+            }
+        };
+
+        let received = futures::select! {
+            chunk = chunks.next() => match chunk {
+                Some(chunk) => {
+                    let (time, bytes) = chunk?;
+                    Received::Bytes(time, bytes)
+                }
+                None => Received::End,
+            },
+            _ = idle_timer.fuse() => Received::Idle,
+            event = events.next() => Received::Event(event),
+        };
+
+        let end = matches!(received, Received::End);
+        if let Received::Bytes(time, ref bytes) = received {
+            // Taps do not wait for stdout, which may be blocked or closed
+            for tap in &taps {
+                let _ = tap.send(Traffic {
+                    direction: Direction::Received,
+                    time: time.instant,
+                    bytes: bytes.clone(),
+                });
+            }
+            let len = translated.len();
+            translator.translate(bytes, &mut translated);
+            held.push(time, translated.len() - len);
+        }
+        // Lines are stamped with the time when their first byte was read, even if held
+        let now = ReadTime::now();
+        let first = held.first().unwrap_or(now);
+        let last = held.last().unwrap_or(now);
+        match received {
+            Received::Bytes(..) => renderer.render(&mut translated, &mut rendered),
+            Received::Idle => {
+                translator.flush(&mut translated);
+                renderer.render(&mut translated, &mut rendered);
+                renderer.flush(&mut rendered);
+            }
+            Received::End => {
+                translator.flush(&mut translated);
+                renderer.render(&mut translated, &mut rendered);
+                renderer.reset(&mut rendered);
+            }
+            Received::Event(Some(ref event)) => {
+                renderer.reset(&mut rendered);
+                match *event {
+                    Display::Reconfigured(_) => {
+                        translator.reset();
+                        translated.clear();
+                    }
+                    Display::SetMode(mode) => {
+                        config.mode = mode;
+                        renderer = config.renderer();
+                    }
+                    _ => {}
+                }
+            }
+            Received::Event(None) => {}
+        }
+        held.release(translated.len() + renderer.held());
+
+        match &mut timestamper {
+            Some(timestamper) => {
+                timestamper.stamp_held(&rendered, first, last, &mut buffer);
+                rendered.clear();
+                write_raw(&mut stdout, &mut buffer).await?;
+            }
+            None => write_raw(&mut stdout, &mut rendered).await?,
+        }
+
+        if let Received::Event(Some(event)) = received {
+            write_display(&mut stdout, event, &mut prompting).await?;
+        }
+
+        stdout
+            .flush()
             .await
-            .context("Cannot read serial port")?;
+            .map_err(|e| stdout_error(e, "Cannot flush stdout"))?;
 
-        if raw {
-            write_raw(&mut stdout, &mut buffer).await?;
-        } else {
-            write_visualized(&mut stdout, &mut buffer, &mut reversed).await?;
+        if end {
+            return Ok(());
+        }
+    }
+}
+
+async fn write_display<W>(mut stdout: W, event: Display, prompting: &mut bool) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    match event {
+        Display::Notice(msg) | Display::Reconfigured(msg) => {
+            // Overwrite the prompt line, or start a new line
+            if *prompting {
+                write_slice(&mut stdout, b"\r\x1b[K").await?;
+                *prompting = false;
+            } else {
+                write_slice(&mut stdout, b"\n").await?;
+            }
+            for line in msg.lines() {
+                write_slice(&mut stdout, format!("*** {}\n", line).as_bytes()).await?;
+            }
+        }
+        Display::Prompt(msg) => {
+            if !*prompting {
+                write_slice(&mut stdout, b"\n").await?;
+                *prompting = true;
+            }
+            write_slice(&mut stdout, format!("\r\x1b[K*** {}", msg).as_bytes()).await?;
         }
+        Display::SetMode(_) => {}
+    }
+    Ok(())
+}
 
-        stdout.flush().await.context("Cannot flush stdout")?;
+/// Error on the serial port, classified when the device is gone.
+fn serial_error(e: std::io::Error, msg: &'static str) -> anyhow::Error {
+    if error::is_disconnected(&e) {
+        anyhow::Error::new(e).context(Error::Disconnected)
+    } else {
+        anyhow::Error::new(e).context(msg)
+    }
+}
+
+/// Error on stdout, classified when it is closed.
+fn stdout_error(e: std::io::Error, msg: &'static str) -> anyhow::Error {
+    if error::is_closed(&e) {
+        anyhow::Error::new(e).context(Error::StdoutClosed)
+    } else {
+        anyhow::Error::new(e).context(msg)
     }
 }
 
@@ -116,7 +1288,7 @@ where
         let len = stdout
             .write_buf(buffer)
             .await
-            .context("Cannot write stdout")?;
+            .map_err(|e| stdout_error(e, "Cannot write stdout"))?;
         if len == 0 {
             bail!("Cannot write stdout anymore");
         }
@@ -129,7 +1301,10 @@ where
     W: AsyncWrite + Unpin,
 {
     while !buffer.is_empty() {
-        let len = stdout.write(buffer).await.context("Cannot write stdout")?;
+        let len = stdout
+            .write(buffer)
+            .await
+            .map_err(|e| stdout_error(e, "Cannot write stdout"))?;
         buffer = &buffer[len..];
         if len == 0 {
             bail!("Cannot write stdout anymore");
@@ -138,91 +1313,189 @@ where
     Ok(())
 }
 
-async fn write_visualized<W, B>(mut stdout: W, buffer: &mut B, reversed: &mut bool) -> Result<()>
+/// State of interactive session.
+struct Interactive {
+    menu: EscapeMenu,
+    /// Quit by this key instead of EOF.
+    eof: Option<u8>,
+    requests: UnboundedSender<Request>,
+    display: UnboundedSender<Display>,
+}
+
+async fn serial_writer<R, W>(
+    mut stdin: R,
+    mut serial_tx: SerialTx<W>,
+    escape_quit: bool,
+    mut encoder: Encoder,
+    omap: OutputMap,
+    mut interactive: Option<Interactive>,
+    traffic: Option<UnboundedSender<Traffic>>,
+) -> Result<()>
 where
+    R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
-    B: Buf + BufMut,
 {
-    for ch in buffer.get_chars() {
-        match ch {
-            GetChars::Char(c) => {
-                if c.is_control() && c != '\n' && c != '\t' {
-                    if !*reversed {
-                        write_slice(&mut stdout, b"\x1b[7m").await?;
-                        *reversed = true;
-                    }
+    let mut buffer = BytesMut::with_capacity(1024);
+    let mut encoded = BytesMut::with_capacity(1024);
+    let mut translator = OutputTranslator::new(omap);
 
-                    if c < '\x20' {
-                        write_slice(&mut stdout, b"^").await?;
-                        write_slice(&mut stdout, &[c as u8 + b'@']).await?;
-                    } else if c == '\x7f' {
-                        write_slice(&mut stdout, b"^?").await?;
-                    } else if c >= '\u{0080}' && c < '\u{00a0}' {
-                        write_slice(&mut stdout, b"^[[").await?;
-                        write_slice(&mut stdout, &[(c as u16 - 0x0080) as u8 + b'@']).await?;
-                    } else {
-                        unreachable!();
+    loop {
+        futures::select! {
+            result = stdin.read_buf(&mut buffer).fuse() => {
+                result.context("Cannot read stdin")?;
+            }
+            // Close the old port as soon as the reader finds it disconnected
+            _ = serial_tx.replaced().fuse() => continue,
+        }
+
+        if !buffer.has_remaining() {
+            // EOF of a terminal in raw mode means that it is gone
+            if interactive.is_some() {
+                return Err(Error::StdinEof.into());
+            }
+            if escape_quit {
+                return Ok(());
+            }
+            // Keep the session without stdin, such as for --exit-on
+            return future::pending().await;
+        }
+
+        let mut quit = false;
+        if let Some(interactive) = &mut interactive {
+            let input = buffer.split();
+            for &b in input.iter() {
+                match interactive.menu.feed(b) {
+                    Some(Event::Send(b)) if Some(b) == interactive.eof => quit = true,
+                    Some(Event::Send(b)) => buffer.put_u8(b),
+                    Some(Event::Command(Command::Quit)) => quit = true,
+                    Some(Event::Command(command)) => {
+                        let _ = interactive.requests.send(Request::new(command));
                     }
-                } else {
-                    if *reversed {
-                        write_slice(&mut stdout, b"\x1b[m").await?;
-                        *reversed = false;
+                    Some(Event::Prompt(msg)) => {
+                        let _ = interactive.display.send(Display::Prompt(msg));
                     }
-
-                    let mut b = [0; 4];
-                    write_slice(&mut stdout, c.encode_utf8(&mut b).as_bytes()).await?;
+                    Some(Event::Notice(msg)) => {
+                        let _ = interactive.display.send(Display::Notice(msg));
+                    }
+                    None => {}
                 }
-            }
-            GetChars::Err(b) => {
-                if !*reversed {
-                    write_slice(&mut stdout, b"\x1b[7m").await?;
-                    *reversed = true;
+                if quit {
+                    break;
                 }
-
-                write_slice(&mut stdout, b"<").await?;
-                let s = format!("{:02X}", b);
-                write_slice(&mut stdout, s.as_bytes()).await?;
-                write_slice(&mut stdout, b">").await?;
             }
         }
-    }
 
-    Ok(())
+        let input = buffer.split();
+        encoder.encode(&input, &mut encoded);
+        translator.translate(&encoded, &mut buffer);
+        encoded.clear();
+        if let Some(traffic) = &traffic {
+            let _ = traffic.send(Traffic {
+                direction: Direction::Sent,
+                time: Instant::now(),
+                bytes: Bytes::copy_from_slice(&buffer),
+            });
+        }
+
+        // Typed bytes are dropped while disconnected
+        serial_tx.write(&buffer, false).await?;
+        buffer.clear();
+
+        if quit {
+            return Ok(());
+        }
+    }
 }
 
-async fn serial_writer<R, W>(mut stdin: R, mut serial_tx: W, escape_quit: bool) -> Result<()>
+/// Run the script with the serial port, and return the exit status.
+///
+/// Received bytes come from the reader, and line settings are changed by the controller.
+async fn run_script<W>(
+    script: Script,
+    mut serial_tx: SerialTx<W>,
+    mut received: UnboundedReceiver<Traffic>,
+    requests: UnboundedSender<Request>,
+    traffic: Option<UnboundedSender<Traffic>>,
+) -> Result<i32>
 where
-    R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let mut buffer = BytesMut::with_capacity(1024);
+    let mut matcher = Matcher::default();
+    let mut default_timeout = script::DEFAULT_TIMEOUT;
+    let mut next = 0;
 
-    loop {
-        stdin
-            .read_buf(&mut buffer)
-            .await
-            .context("Cannot read stdin")?;
-
-        if !buffer.has_remaining() {
-            // EOF
-            if escape_quit {
-                return Ok(());
+    while let Some(line) = script.lines.get(next) {
+        next += 1;
+        serial_tx.update();
+        match &line.statement {
+            Statement::Send(bytes) => {
+                if let Some(traffic) = &traffic {
+                    let _ = traffic.send(Traffic {
+                        direction: Direction::Sent,
+                        time: Instant::now(),
+                        bytes: Bytes::copy_from_slice(bytes),
+                    });
+                }
+                serial_tx.write(bytes, true).await?;
             }
-        }
-
-        while buffer.has_remaining() {
-            let len = serial_tx
-                .write_buf(&mut buffer)
-                .await
-                .context("Cannot write serial port")?;
-            if len == 0 {
-                bail!("Cannot write serial port anymore");
+            Statement::Expect(expect) => {
+                let timeout = expect.timeout.unwrap_or(default_timeout);
+                let deadline = tokio::time::Instant::now() + timeout;
+                let patterns = expect.patterns.iter().map(|(pattern, _)| pattern);
+                loop {
+                    if let Some(i) = matcher.find(patterns.clone()) {
+                        if let Some(target) = expect.patterns[i].1 {
+                            next = target;
+                        }
+                        break;
+                    }
+                    let traffic = serial_tx.updating(received.next());
+                    match tokio::time::timeout_at(deadline, traffic).await {
+                        Ok(Some(traffic)) => matcher.push(&traffic.bytes),
+                        Ok(None) => {
+                            return Err(anyhow::anyhow!("Reader ended"))
+                                .context(Error::Disconnected)
+                                .with_context(|| format!("Line {}", line.number));
+                        }
+                        Err(_) => match expect.on_timeout {
+                            Some(target) => {
+                                next = target;
+                                break;
+                            }
+                            None => {
+                                return Err(Error::Timeout(format!(
+                                    "Script timed out at line {} after {:?}",
+                                    line.number, timeout
+                                ))
+                                .into());
+                            }
+                        },
+                    }
+                }
+            }
+            Statement::Sleep(duration) => {
+                serial_tx.updating(tokio::time::delay_for(*duration)).await
+            }
+            Statement::SetTimeout(timeout) => default_timeout = *timeout,
+            Statement::Set(command) => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                let request = Request {
+                    command: command.clone(),
+                    reply: Some(reply_tx),
+                };
+                if requests.send(request).is_err() {
+                    bail!("Line {}: Controller ended", line.number);
+                }
+                match reply_rx.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(msg)) => bail!("Line {}: {}", line.number, msg),
+                    Err(_) => bail!("Line {}: Controller ended", line.number),
+                }
             }
+            Statement::Goto(target) => next = *target,
+            Statement::Exit(status) => return Ok(*status),
         }
-
-        serial_tx
-            .flush()
-            .await
-            .context("Cannot flush serial port")?;
     }
+
+    Ok(0)
 }
@@ -1,56 +1,278 @@
 //! Command line parser
 
-use anyhow::Result;
-use structopt::StructOpt;
+use anyhow::{anyhow, bail, Context as _, Result};
+use std::{ffi::OsString, str::FromStr, time::Duration};
+use structopt::{
+    clap::{self, AppSettings},
+    StructOpt,
+};
 use tokio_serial as serial;
 
-fn data_bits_from_str(s: &str) -> Result<serial::DataBits> {
+use crate::{
+    config::{self, Config},
+    encoding::Encoding,
+    error::{EXIT_FAIL, EX_CONFIG, EX_USAGE},
+    port::{LineLevel, Parity, ResetPulse, StopBits},
+    render::{AnsiMode, CtrlSet, CtrlStyle, OutputMode},
+    timestamp::TimestampMode,
+    translate::{InputMap, OutputMap},
+    watch::ExitPattern,
+};
+
+pub(crate) fn data_bits_from_str(s: &str) -> Result<serial::DataBits> {
     use serial::DataBits::*;
     match s {
         "5" => Ok(Five),
         "6" => Ok(Six),
         "7" => Ok(Seven),
         "8" => Ok(Eight),
-        _ => unreachable!(),
+        _ => bail!("Unknown data bits: {}", s),
     }
 }
 
-fn parity_from_str(s: &str) -> Result<serial::Parity> {
-    use serial::Parity::*;
+pub(crate) fn parity_from_str(s: &str) -> Result<Parity> {
     match s {
-        "none" => Ok(None),
-        "odd" => Ok(Odd),
-        "even" => Ok(Even),
-        _ => unreachable!(),
+        "none" => Ok(Parity::None),
+        "odd" => Ok(Parity::Odd),
+        "even" => Ok(Parity::Even),
+        "mark" => Ok(Parity::Mark),
+        "space" => Ok(Parity::Space),
+        _ => bail!("Unknown parity: {}", s),
     }
 }
 
-fn stop_bits_from_str(s: &str) -> Result<serial::StopBits> {
-    use serial::StopBits::*;
+pub(crate) fn stop_bits_from_str(s: &str) -> Result<StopBits> {
+    use StopBits::*;
     match s {
         "1" => Ok(One),
+        "1.5" => Ok(OnePointFive),
         "2" => Ok(Two),
-        _ => unreachable!(),
+        _ => bail!("Unknown stop bits: {}", s),
     }
 }
 
-fn flow_control_from_str(s: &str) -> Result<serial::FlowControl> {
+pub(crate) fn flow_control_from_str(s: &str) -> Result<serial::FlowControl> {
     use serial::FlowControl::*;
     match s {
         "none" => Ok(None),
         "software" => Ok(Software),
         "hardware" => Ok(Hardware),
-        _ => unreachable!(),
+        _ => bail!("Unknown flow control: {}", s),
+    }
+}
+
+fn escape_char_from_str(s: &str) -> Result<u8> {
+    let key = match s.strip_prefix("C-").or_else(|| s.strip_prefix('^')) {
+        Some(key) => key,
+        None => bail!("Escape key must be a control key such as C-t or ^T: {}", s),
+    };
+    match key.as_bytes() {
+        &[b] if (b'@'..=b'_').contains(&b.to_ascii_uppercase()) => {
+            Ok(b.to_ascii_uppercase() - b'@')
+        }
+        _ => bail!("Unknown control key: {}", s),
+    }
+}
+
+/// Parse a size in bytes with an optional suffix: K, M or G.
+pub(crate) fn size_from_str(s: &str) -> Result<u64> {
+    let (number, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => bail!("Unknown size unit: {}", s),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 && n.leading_zeros() >= shift => Ok(n << shift),
+        _ => bail!("Invalid size: {}", s),
+    }
+}
+
+/// Parse a duration with an optional suffix: ms, s, m, h or d. Seconds by default.
+pub(crate) fn duration_from_str(s: &str) -> Result<Duration> {
+    let (number, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let millis = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => bail!("Unknown duration unit: {}", s),
+    };
+    match number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(millis))
+    {
+        Some(n) if n > 0 => Ok(Duration::from_millis(n)),
+        _ => bail!("Invalid duration: {}", s),
+    }
+}
+
+pub(crate) fn line_level_from_str(s: &str) -> Result<LineLevel> {
+    match s {
+        "on" => Ok(LineLevel::On),
+        "off" => Ok(LineLevel::Off),
+        "keep" => Ok(LineLevel::Keep),
+        _ => bail!("Unknown line level: {} (on, off or keep)", s),
+    }
+}
+
+pub(crate) fn reset_pulse_from_str(s: &str) -> Result<ResetPulse> {
+    match s {
+        "dtr" => Ok(ResetPulse::Dtr),
+        "rts" => Ok(ResetPulse::Rts),
+        "esp32" => Ok(ResetPulse::Esp32),
+        "esp32-boot" => Ok(ResetPulse::Esp32Boot),
+        _ => bail!("Unknown reset pulse: {}", s),
+    }
+}
+
+fn fail_pattern_from_str(s: &str) -> Result<ExitPattern> {
+    ExitPattern::new(EXIT_FAIL, s)
+}
+
+/// Line settings in the compact notation: the baud rate and the frame (data bits, parity and stop
+/// bits), separated by `,`, `:` or a space. Either can be omitted.
+///
+/// ```
+///     # use serialcat::opt::Line;
+///     # use serialcat::port::{Parity, StopBits};
+///     # use tokio_serial::DataBits;
+///
+///     let line: Line = "9600,7E2".parse().unwrap();
+///     assert_eq!(line.baud_rate, Some(9600));
+///     assert_eq!(line.frame, Some((DataBits::Seven, Parity::Even, StopBits::Two)));
+///     assert_eq!("8N1".parse::<Line>().unwrap().baud_rate, None);
+///     assert_eq!(
+///         "250000,8N2".parse::<Line>().unwrap().frame,
+///         Some((DataBits::Eight, Parity::None, StopBits::Two))
+///     );
+///     assert!("9600,8N1.5".parse::<Line>().is_err());
+///     assert!("115200,8X1".parse::<Line>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line {
+    /// Baud rate, if given.
+    pub baud_rate: Option<u32>,
+    /// Data bits, parity and stop bits, if given.
+    pub frame: Option<(serial::DataBits, Parity, StopBits)>,
+}
+
+impl FromStr for Line {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Line> {
+        let (baud_rate, frame) = match s.find(&[',', ':', ' '][..]) {
+            Some(i) => (Some(&s[..i]), Some(&s[i + 1..])),
+            // A frame has a parity letter, while a baud rate has only digits
+            None if s.bytes().any(|b| b.is_ascii_alphabetic()) => (None, Some(s)),
+            None => (Some(s), None),
+        };
+        let baud_rate = match baud_rate {
+            Some(arg) => match arg.parse() {
+                Ok(baud_rate) if baud_rate > 0 => Some(baud_rate),
+                _ => bail!("Invalid baud rate in {}: {}", s, arg),
+            },
+            None => None,
+        };
+        let frame = match frame {
+            Some(arg) => {
+                Some(frame_from_str(arg).with_context(|| format!("Invalid frame in {}", s))?)
+            }
+            None => None,
+        };
+        Ok(Line { baud_rate, frame })
+    }
+}
+
+impl Line {
+    /// Split the line settings from the port, such as `/dev/ttyUSB0@115200:8E1`.
+    ///
+    /// The port is returned as is without `@` followed by a digit, such as `@PROFILE`.
+    pub fn split_port(port: &str) -> Result<(&str, Option<Line>)> {
+        match port.rfind('@') {
+            Some(i) if i > 0 && port[i + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                let line = port[i + 1..]
+                    .parse()
+                    .with_context(|| format!("Invalid line settings of the port: {}", port))?;
+                Ok((&port[..i], Some(line)))
+            }
+            _ => Ok((port, None)),
+        }
     }
 }
 
+/// Parse a frame such as `8N1`.
+fn frame_from_str(s: &str) -> Result<(serial::DataBits, Parity, StopBits)> {
+    let mut chars = s.chars();
+    let (data_bits, parity) = match (chars.next(), chars.next()) {
+        (Some(data_bits), Some(parity)) => (data_bits, parity),
+        _ => bail!(
+            "Frame must be data bits, parity and stop bits such as 8N1: {}",
+            s
+        ),
+    };
+    let stop_bits = chars.as_str();
+    let data_bits = data_bits_from_str(&data_bits.to_string())?;
+    let parity = match parity.to_ascii_uppercase() {
+        'N' => Parity::None,
+        'O' => Parity::Odd,
+        'E' => Parity::Even,
+        'M' => Parity::Mark,
+        'S' => Parity::Space,
+        _ => bail!("Unknown parity: {} (N, O, E, M or S)", parity),
+    };
+    let stop_bits = match stop_bits {
+        "" => bail!(
+            "Frame must be data bits, parity and stop bits such as 8N1: {}",
+            s
+        ),
+        _ => stop_bits_from_str(stop_bits)?,
+    };
+    if stop_bits == StopBits::OnePointFive && data_bits != serial::DataBits::Five {
+        bail!("1.5 stop bits need 5 data bits: {}", s);
+    }
+    Ok((data_bits, parity, stop_bits))
+}
+
 /// Command line options.
 ///
 /// [parse_args](fn.parse_args.html) parses command line arguments and returns this struct.
 #[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+// Later options override earlier ones, to override a profile by the command line
+#[structopt(setting = AppSettings::AllArgsOverrideSelf)]
 pub struct Opt {
-    #[structopt(help = "Serial port device", name = "port")]
-    pub port: String,
+    // Required unless a subcommand or --list is given, checked by parse_args_from
+    #[structopt(
+        help = "Serial port device: a path, a glob, usb:VID[:PID], serial:GLOB (USB serial number) or by-id:GLOB (/dev/serial/by-id). Must match exactly one port. @PROFILE selects the profile instead",
+        name = "port"
+    )]
+    pub port: Option<String>,
+
+    #[structopt(
+        long,
+        name = "PROFILE",
+        help = "Apply the profile in ./.serialcat.toml or $XDG_CONFIG_HOME/serialcat/config.toml. Options given on the command line override it"
+    )]
+    pub profile: Option<String>,
+
+    #[structopt(
+        long,
+        help = "List serial ports with their type, USB VID:PID, manufacturer, product, serial number and /dev/serial/by-id path, and exit"
+    )]
+    pub list: bool,
+
+    #[structopt(long, requires = "list", help = "Print the list of --list as JSON")]
+    pub json: bool,
 
     #[structopt(
         long,
@@ -75,24 +297,32 @@ pub struct Opt {
     #[structopt(
         long,
         short,
-        possible_values(&["none", "odd", "even"]),
+        possible_values(&["none", "odd", "even", "mark", "space"]),
         default_value = "none",
         help = "Parity of serial port",
         name = "PARITY",
         parse(try_from_str = parity_from_str)
     )]
-    pub parity: serial::Parity,
+    pub parity: Parity,
 
     #[structopt(
         long,
         short,
-        possible_values(&["1", "2"]),
+        possible_values(&["1", "1.5", "2"]),
         default_value = "1",
-        help = "Stop bits of serial port",
+        help = "Stop bits of serial port. 1.5 needs 5 data bits",
         name = "STOP_BITS",
         parse(try_from_str = stop_bits_from_str)
     )]
-    pub stop_bits: serial::StopBits,
+    pub stop_bits: StopBits,
+
+    #[structopt(
+        long,
+        name = "LINE",
+        help = "Baud rate, data bits, parity and stop bits such as 115200,8N1 or 9600:7E2, overriding the options given before. Also given as PORT@LINE, which overrides all",
+        parse(try_from_str)
+    )]
+    pub line: Option<Line>,
 
     #[structopt(
         long,
@@ -105,67 +335,586 @@ pub struct Opt {
     )]
     pub flow_control: serial::FlowControl,
 
+    #[structopt(
+        long,
+        possible_values(&["on", "off", "keep"]),
+        help = "Set DTR right after opening the serial port, or keep it as the kernel set it on open. The kernel raises DTR on open, so setting it off still makes an edge resetting some boards; see --no-hupcl",
+        name = "DTR",
+        parse(try_from_str = line_level_from_str)
+    )]
+    pub dtr: Option<LineLevel>,
+
+    #[structopt(
+        long,
+        possible_values(&["on", "off", "keep"]),
+        help = "Set RTS right after opening the serial port, or keep it as the kernel set it on open",
+        name = "RTS",
+        parse(try_from_str = line_level_from_str)
+    )]
+    pub rts: Option<LineLevel>,
+
+    #[structopt(
+        long,
+        help = "Clear HUPCL on the serial port so that DTR and RTS stay on after exit, and the next open does not reset a board wired to them. WARNING: the change stays on the device for every program using it, until `stty -F <PORT> hupcl`"
+    )]
+    pub no_hupcl: bool,
+
+    #[structopt(
+        long,
+        possible_values(&["dtr", "rts", "esp32", "esp32-boot"]),
+        default_value = "dtr",
+        help = "Sequence of DTR and RTS to reset the board from the escape menu or by reset without an argument: a pulse of DTR or RTS, or the ESP32 auto-reset to run the firmware or to enter the bootloader",
+        name = "RESET",
+        parse(try_from_str = reset_pulse_from_str)
+    )]
+    pub reset: ResetPulse,
+
+    #[structopt(
+        long,
+        help = "Keep running when the serial port disappears, and reopen it when the same device (the path or the USB serial number) comes back, with the line settings given at start"
+    )]
+    pub reconnect: bool,
+
+    #[structopt(
+        long,
+        name = "WAIT_TIMEOUT",
+        require_equals = true,
+        min_values = 0,
+        max_values = 1,
+        help = "Wait for the serial port to appear and be accessible before opening it, forever or up to the timeout given as --wait=30s (ms, s, m, h or d)",
+        parse(try_from_str = duration_from_str)
+    )]
+    // Empty to wait forever. Option<Option<_>> does not work with a custom parser
+    pub wait: Option<Vec<Duration>>,
+
     #[structopt(
         long,
         short,
-        help = "Do not visualize control characters and invalid UTF-8 sequence"
+        possible_values(&["visualized", "raw", "hex", "auto"]),
+        default_value = "visualized",
+        help = "Display received bytes: visualized (control characters and invalid UTF-8 sequence), raw (as is), hex (hexdump) or auto (text with hexdump blocks of binary data)",
+        name = "OUTPUT"
+    )]
+    pub output: OutputMode,
+
+    #[structopt(
+        long,
+        name = "BYTES",
+        default_value = "4",
+        help = "In auto output, switch to hexdump after this many non-printable bytes in a row, and back to text after as many printable characters"
     )]
-    pub raw: bool,
+    pub binary_threshold: usize,
+
+    #[structopt(
+        long,
+        possible_values(&["caret", "escape", "picture", "mnemonic"]),
+        default_value = "caret",
+        help = "Notation of control characters: caret (^M), escape (\\x0d), picture (Unicode Control Pictures) or mnemonic (<CR>)",
+        name = "STYLE"
+    )]
+    pub ctrl_style: CtrlStyle,
+
+    #[structopt(long, help = "Do not show control characters in reverse video")]
+    pub no_color: bool,
+
+    #[structopt(
+        long,
+        name = "CHARS",
+        default_value = "LF,HT",
+        help = "Comma separated mnemonics of control characters written as is, or none"
+    )]
+    pub pass_through: CtrlSet,
+
+    #[structopt(
+        long,
+        possible_values(&["visualize", "pass", "strip"]),
+        default_value = "visualize",
+        help = "Handle ANSI escape sequences (such as colors) received: visualize, pass (write as is) or strip",
+        name = "ANSI"
+    )]
+    pub ansi: AnsiMode,
+
+    #[structopt(
+        long,
+        possible_values(&["utf-8", "shift_jis", "euc-jp", "latin1", "cp437"]),
+        default_value = "utf-8",
+        help = "Text encoding of the serial port, used to display received bytes and to send typed text",
+        name = "ENCODING"
+    )]
+    pub encoding: Encoding,
 
     #[structopt(
         long,
         short,
-        help = "Quit when input EOF from stdin. Currently, do not quit if last character is not newline"
+        help = "Quit when input EOF from stdin. In interactive session, quit by the EOF character (usually Ctrl-D)"
     )]
     pub escape_quit: bool,
+
+    #[structopt(
+        long,
+        name = "KEY",
+        default_value = "C-t",
+        help = "Escape key to command menu in interactive session",
+        parse(try_from_str = escape_char_from_str)
+    )]
+    pub escape_char: u8,
+
+    #[structopt(
+        long,
+        name = "MAPPINGS",
+        default_value = "",
+        help = "Translate outgoing bytes by comma separated mappings: crlf, crcrlf, igncr, lfcr, lfcrlf, ignlf, delbs, bsdel"
+    )]
+    pub omap: OutputMap,
+
+    #[structopt(
+        long,
+        possible_values(&["none", "crlf", "cr", "ignore-cr", "lfcr"]),
+        default_value = "none",
+        help = "Translate incoming line endings: crlf (CRLF to LF), cr (CR and CRLF to LF), ignore-cr (drop CR) or lfcr (LFCR to LF). With crlf, a CR received last is held until the next byte, or displayed after --idle",
+        name = "MAPPING"
+    )]
+    pub imap: InputMap,
+
+    #[structopt(
+        long,
+        alias = "hex-idle",
+        name = "MILLISECONDS",
+        default_value = "100",
+        help = "Time without received bytes to display the ones held: a CR held by --imap crlf, a partial hexdump row, the hexdump block of auto output, and an incomplete ANSI escape sequence"
+    )]
+    pub idle: u64,
+
+    #[structopt(
+        long,
+        short,
+        possible_values(&["wall", "monotonic", "delta"]),
+        help = "Print timestamp at the beginning of every received line: wall (ISO 8601), monotonic (seconds since start) or delta (seconds since the previous line)",
+        name = "MODE"
+    )]
+    pub timestamp: Option<TimestampMode>,
+
+    #[structopt(
+        long,
+        name = "SOCKET",
+        help = "Listen commands on the unix domain socket to control the session"
+    )]
+    pub control: Option<String>,
+
+    #[structopt(
+        long,
+        name = "SCRIPT",
+        help = "Run the expect-style script instead of reading stdin, and exit with its status"
+    )]
+    pub script: Option<String>,
+
+    #[structopt(
+        long,
+        name = "[STATUS:]REGEX",
+        number_of_values = 1,
+        help = "Exit with the status (0 by default) when a received line matches the pattern. Can be given multiple times"
+    )]
+    pub exit_on: Vec<ExitPattern>,
+
+    #[structopt(
+        long,
+        name = "REGEX",
+        number_of_values = 1,
+        help = "Exit with status 1 when a received line matches the pattern. Checked before --exit-on. Can be given multiple times",
+        parse(try_from_str = fail_pattern_from_str)
+    )]
+    pub fail_on: Vec<ExitPattern>,
+
+    #[structopt(
+        long,
+        name = "TIMEOUT",
+        help = "Exit with status 124 after the duration, such as 30s (ms, s, m, h or d)",
+        parse(try_from_str = duration_from_str)
+    )]
+    pub timeout: Option<Duration>,
+
+    #[structopt(
+        long,
+        name = "PATH",
+        help = "Write received bytes to the log file as is"
+    )]
+    pub log: Option<String>,
+
+    #[structopt(long, help = "Also write transmitted bytes to the log file")]
+    pub log_tx: bool,
+
+    #[structopt(long, help = "Append to the log file instead of truncating it")]
+    pub log_append: bool,
+
+    #[structopt(
+        long,
+        name = "SIZE",
+        help = "Rotate the log file before it exceeds the size, such as 10M (K, M or G)",
+        parse(try_from_str = size_from_str)
+    )]
+    pub log_rotate_size: Option<u64>,
+
+    #[structopt(
+        long,
+        name = "DURATION",
+        help = "Rotate the log file after the duration, such as 1h (s, m, h or d)",
+        parse(try_from_str = duration_from_str)
+    )]
+    pub log_rotate_interval: Option<Duration>,
+
+    #[structopt(
+        long,
+        name = "COUNT",
+        default_value = "5",
+        help = "Number of rotated log files to keep, as <PATH>.1 (newest) to <PATH>.<COUNT>"
+    )]
+    pub log_keep: usize,
+
+    #[structopt(
+        long,
+        name = "FILE",
+        help = "Record received and sent bytes with timestamps to the capture file, to replay by `sc replay`"
+    )]
+    pub capture: Option<String>,
+
+    #[structopt(
+        long,
+        name = "PCAP",
+        help = "Write received and sent bytes as packets to the pcapng file, to analyze with Wireshark"
+    )]
+    pub pcap: Option<String>,
+
+    #[structopt(
+        long,
+        name = "LINKTYPE",
+        default_value = "147",
+        help = "Link type of packets in the pcapng file, such as 147 (USER0) to 162 (USER15)"
+    )]
+    pub pcap_link_type: u16,
+
+    #[structopt(
+        long,
+        name = "MS",
+        help = "Merge bytes in the same direction into a pcapng packet until idle for this time in milliseconds"
+    )]
+    pub pcap_split_idle: Option<u64>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Subcommand>,
+}
+
+impl Opt {
+    /// Override the line settings given.
+    fn apply_line(&mut self, line: Line) {
+        if let Some(baud_rate) = line.baud_rate {
+            self.baud_rate = baud_rate;
+        }
+        if let Some((data_bits, parity, stop_bits)) = line.frame {
+            self.data_bits = data_bits;
+            self.parity = parity;
+            self.stop_bits = stop_bits;
+        }
+    }
+}
+
+/// Commands to run instead of a session.
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+pub enum Subcommand {
+    #[structopt(
+        about = "Display a capture file recorded by --capture, with the display options given before `replay`"
+    )]
+    Replay {
+        #[structopt(help = "Capture file", name = "FILE")]
+        file: String,
+
+        #[structopt(long, help = "Wait between chunks as recorded")]
+        realtime: bool,
+
+        #[structopt(long, help = "Also display sent bytes, as if the device echoed them")]
+        sent: bool,
+    },
 }
 
 /// Parse command line arguments.
 ///
-/// This function parses command line arguments and returns [Opt](struct.Opt.html).
+/// This function parses command line arguments, applying the profile from
+/// [configuration files](../config/fn.paths.html), and returns [Opt](struct.Opt.html).
 /// If command line arguments are help, version or invalid sequence, this function prints messages
-/// and exits process immediately.
+/// and exits process immediately, with [EX_USAGE](../error/constant.EX_USAGE.html) if invalid, or
+/// [EX_CONFIG](../error/constant.EX_CONFIG.html) if configuration files are invalid.
 pub fn parse_args() -> Opt {
-    Opt::from_args()
+    parse_args_from(std::env::args_os(), || Config::load(&config::paths())).unwrap_or_else(|e| {
+        match e.downcast::<clap::Error>() {
+            Ok(e) => match e.kind {
+                clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => e.exit(),
+                _ => {
+                    eprintln!("{}", e.message);
+                    std::process::exit(EX_USAGE);
+                }
+            },
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(EX_CONFIG);
+            }
+        }
+    })
+}
+
+/// Options turning off flags, such as set by a profile, with the names of the flags.
+const NEGATED_FLAGS: &[(&str, &str)] = &[
+    ("no-reconnect", "reconnect"),
+    ("color", "no-color"),
+    ("no-escape-quit", "escape-quit"),
+    ("no-log-tx", "log-tx"),
+    ("no-log-append", "log-append"),
+];
+
+/// Parse arguments, applying `--line` over the separate line options given before it, and `-r`
+/// as `-o raw`.
+fn parse<I>(args: I) -> Result<Opt, clap::Error>
+where
+    I: IntoIterator,
+    I::Item: Into<OsString> + Clone,
+{
+    // Hidden, for scripts written before --output
+    let raw = clap::Arg::with_name("RAW")
+        .short("r")
+        .long("raw")
+        .hidden(true)
+        .help("Same as --output raw");
+    let mut app = Opt::clap().arg(raw);
+    // Hidden, to turn off flags set by a profile
+    for &(long, flag) in NEGATED_FLAGS {
+        let negation = clap::Arg::with_name(long)
+            .long(long)
+            .hidden(true)
+            .overrides_with(flag);
+        app = app.arg(negation);
+    }
+    let matches = app.get_matches_from_safe(args)?;
+    let mut opt = Opt::from_clap(&matches);
+    // Index of the last occurrence, or None for the default value
+    let last = |name| {
+        if matches.occurrences_of(name) > 0 {
+            matches.indices_of(name).and_then(Iterator::max)
+        } else {
+            None
+        }
+    };
+    if last("RAW") > last("OUTPUT") {
+        opt.output = OutputMode::Raw;
+    }
+    if let Some(line) = opt.line {
+        let index = last("LINE");
+        if let Some(baud_rate) = line.baud_rate.filter(|_| last("BAUD") < index) {
+            opt.baud_rate = baud_rate;
+        }
+        if let Some((data_bits, parity, stop_bits)) = line.frame {
+            if last("DATA_BITS") < index {
+                opt.data_bits = data_bits;
+            }
+            if last("PARITY") < index {
+                opt.parity = parity;
+            }
+            if last("STOP_BITS") < index {
+                opt.stop_bits = stop_bits;
+            }
+        }
+    }
+    Ok(opt)
+}
+
+/// Parse arguments, applying the profile selected by `--profile NAME` or `@NAME` as the port.
+///
+/// Options are merged in this order, each overriding the former: the defaults, the profile, and
+/// the arguments. Lists such as `--exit-on` in the arguments replace the ones in the profile, and
+/// flags set by the profile are turned off by `--no-FLAG`, or `--color` for `no-color`.
+/// `load_config` is called only when a profile is selected.
+///
+/// Errors in the arguments are [clap::Error](../../clap/struct.Error.html).
+pub fn parse_args_from<I, F>(args: I, load_config: F) -> Result<Opt>
+where
+    I: IntoIterator,
+    I::Item: Into<OsString>,
+    F: FnOnce() -> Result<Config>,
+{
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    let mut opt = parse(&args)?;
+
+    let at_name = opt.port.as_deref().and_then(|port| port.strip_prefix('@'));
+    let name = match (opt.profile.as_deref(), at_name) {
+        (Some(_), Some(_)) => {
+            return Err(clap::Error::with_description(
+                "Profile is given by both --profile and @PROFILE",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .into())
+        }
+        (Some(name), None) | (None, Some(name)) => Some(name.to_owned()),
+        (None, None) => None,
+    };
+    if let Some(name) = name {
+        let config = load_config()?;
+        let profile = config.profile(&name)?;
+        let program = args.first().cloned().unwrap_or_else(|| "sc".into());
+        // Check the profile alone, not to blame the command line for errors in the profile
+        Opt::from_iter_safe(Some(program.clone()).into_iter().chain(profile.args())).map_err(
+            |e| {
+                let msg = e.message.lines().next().unwrap_or_default();
+                anyhow!("Invalid profile: {}: {}", name, msg)
+            },
+        )?;
+
+        // Lists are collected from all occurrences, so drop the ones given in the arguments
+        let given = |long: &str| match long {
+            "exit-on" => !opt.exit_on.is_empty(),
+            "fail-on" => !opt.fail_on.is_empty(),
+            "wait" => opt.wait.is_some(),
+            _ => false,
+        };
+        let profile_args = profile
+            .options
+            .iter()
+            .filter(|(long, _)| !given(long))
+            .flat_map(|(_, args)| args)
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        let merged = Some(program)
+            .into_iter()
+            .chain(profile_args)
+            .chain(args.into_iter().skip(1));
+        opt = parse(merged)?;
+        if opt.port.iter().all(|port| port.starts_with('@')) {
+            opt.port = profile.port.clone();
+        }
+    }
+
+    if let Some(port) = opt.port.take() {
+        let (port, line) = Line::split_port(&port).map_err(|e| {
+            clap::Error::with_description(&format!("{:#}", e), clap::ErrorKind::InvalidValue)
+        })?;
+        if let Some(line) = line {
+            opt.apply_line(line);
+        }
+        opt.port = Some(port.to_owned());
+    }
+
+    // UARTs cannot send 1.5 stop bits with more data bits, so tell it before opening the port
+    if opt.stop_bits == StopBits::OnePointFive && opt.data_bits != serial::DataBits::Five {
+        return Err(clap::Error::with_description(
+            "1.5 stop bits need 5 data bits",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .into());
+    }
+
+    if opt.port.is_none() && !opt.list && opt.command.is_none() {
+        return Err(clap::Error::with_description(
+            "No serial port given",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .into());
+    }
+    Ok(opt)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use serial::{DataBits, FlowControl, Parity, StopBits};
+    use crate::port::{LineLevel, Parity, ResetPulse, StopBits};
+    use serial::{DataBits, FlowControl};
 
     #[test]
     fn args() {
         let name = "sc";
         let default_port = "/dev/ttyACM0";
         let default = Opt {
-            port: default_port.to_owned(),
+            port: Some(default_port.to_owned()),
+            profile: None,
+            list: false,
+            json: false,
             baud_rate: 9600,
             data_bits: DataBits::Eight,
             parity: Parity::None,
             stop_bits: StopBits::One,
+            line: None,
             flow_control: FlowControl::None,
-            raw: false,
+            dtr: None,
+            rts: None,
+            no_hupcl: false,
+            reset: ResetPulse::Dtr,
+            reconnect: false,
+            wait: None,
+            output: OutputMode::Visualized,
+            binary_threshold: 4,
+            ctrl_style: CtrlStyle::Caret,
+            no_color: false,
+            pass_through: CtrlSet::default(),
+            ansi: AnsiMode::Visualize,
+            encoding: Encoding::Utf8,
             escape_quit: false,
+            escape_char: 0x14,
+            omap: OutputMap::default(),
+            imap: InputMap::None,
+            idle: 100,
+            timestamp: None,
+            control: None,
+            script: None,
+            exit_on: Vec::new(),
+            fail_on: Vec::new(),
+            timeout: None,
+            log: None,
+            log_tx: false,
+            log_append: false,
+            log_rotate_size: None,
+            log_rotate_interval: None,
+            log_keep: 5,
+            capture: None,
+            pcap: None,
+            pcap_link_type: 147,
+            pcap_split_idle: None,
+            command: None,
         };
 
         // default
-        let args = Opt::from_iter_safe(&[&name, &default_port]).unwrap();
+        let args = Opt::from_iter_safe(&[name, default_port]).unwrap();
         assert_eq!(args, default);
 
         // other port name
-        let args = Opt::from_iter_safe(&[&name, "/dev/ttyACM1"]).unwrap();
+        let args = Opt::from_iter_safe(&[name, "/dev/ttyACM1"]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                port: Some("/dev/ttyACM1".to_owned()),
+                ..default.clone()
+            }
+        );
+
+        // list
+        let args = Opt::from_iter_safe(&[name, "--list"]).unwrap();
         assert_eq!(
             args,
             Opt {
-                port: "/dev/ttyACM1".to_owned(),
+                port: None,
+                list: true,
                 ..default.clone()
             }
         );
+        let args = Opt::from_iter_safe(&[name, "--list", "--json"]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                port: None,
+                list: true,
+                json: true,
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--json", default_port]).unwrap_err();
 
         // baud rate
-        let args = Opt::from_iter_safe(&[&name, "-b", "115200", &default_port]).unwrap();
+        let args = Opt::from_iter_safe(&[name, "-b", "115200", default_port]).unwrap();
         assert_eq!(
             args,
             Opt {
@@ -181,7 +930,7 @@ mod tests {
             ("7", DataBits::Seven),
             ("8", DataBits::Eight),
         ] {
-            let args = Opt::from_iter_safe(&[&name, "-d", arg, &default_port]).unwrap();
+            let args = Opt::from_iter_safe(&[name, "-d", arg, default_port]).unwrap();
             assert_eq!(
                 args,
                 Opt {
@@ -190,16 +939,18 @@ mod tests {
                 }
             );
         }
-        Opt::from_iter_safe(&[&name, "-d", "4", &default_port]).unwrap_err();
-        Opt::from_iter_safe(&[&name, "-d", "9", &default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "-d", "4", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "-d", "9", default_port]).unwrap_err();
 
         // parity
         for (arg, enm) in &[
             ("none", Parity::None),
             ("odd", Parity::Odd),
             ("even", Parity::Even),
+            ("mark", Parity::Mark),
+            ("space", Parity::Space),
         ] {
-            let args = Opt::from_iter_safe(&[&name, "-p", arg, &default_port]).unwrap();
+            let args = Opt::from_iter_safe(&[name, "-p", arg, default_port]).unwrap();
             assert_eq!(
                 args,
                 Opt {
@@ -208,11 +959,15 @@ mod tests {
                 }
             );
         }
-        Opt::from_iter_safe(&[&name, "-p", "crc", &default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "-p", "crc", default_port]).unwrap_err();
 
         // stop bits
-        for (arg, enm) in &[("1", StopBits::One), ("2", StopBits::Two)] {
-            let args = Opt::from_iter_safe(&[&name, "-s", arg, &default_port]).unwrap();
+        for (arg, enm) in &[
+            ("1", StopBits::One),
+            ("1.5", StopBits::OnePointFive),
+            ("2", StopBits::Two),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "-s", arg, default_port]).unwrap();
             assert_eq!(
                 args,
                 Opt {
@@ -221,8 +976,8 @@ mod tests {
                 }
             );
         }
-        Opt::from_iter_safe(&[&name, "-s", "0", &default_port]).unwrap_err();
-        Opt::from_iter_safe(&[&name, "-s", "3", &default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "-s", "0", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "-s", "3", default_port]).unwrap_err();
 
         // flow control
         for (arg, enm) in &[
@@ -230,7 +985,7 @@ mod tests {
             ("software", FlowControl::Software),
             ("hardware", FlowControl::Hardware),
         ] {
-            let args = Opt::from_iter_safe(&[&name, "-f", arg, &default_port]).unwrap();
+            let args = Opt::from_iter_safe(&[name, "-f", arg, default_port]).unwrap();
             assert_eq!(
                 args,
                 Opt {
@@ -239,20 +994,193 @@ mod tests {
                 }
             );
         }
-        Opt::from_iter_safe(&[&name, "-f", "rts", &default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "-f", "rts", default_port]).unwrap_err();
 
-        // raw
-        let args = Opt::from_iter_safe(&[&name, "-r", &default_port]).unwrap();
+        // modem lines
+        let args =
+            Opt::from_iter_safe(&[name, "--dtr", "off", "--rts=keep", default_port]).unwrap();
         assert_eq!(
             args,
             Opt {
-                raw: true,
+                dtr: Some(LineLevel::Off),
+                rts: Some(LineLevel::Keep),
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--dtr", "low", default_port]).unwrap_err();
+        let args = Opt::from_iter_safe(&[name, "--no-hupcl", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                no_hupcl: true,
                 ..default.clone()
             }
         );
 
+        // reset
+        for (arg, enm) in &[
+            ("dtr", ResetPulse::Dtr),
+            ("rts", ResetPulse::Rts),
+            ("esp32", ResetPulse::Esp32),
+            ("esp32-boot", ResetPulse::Esp32Boot),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "--reset", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    reset: *enm,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "--reset", "en", default_port]).unwrap_err();
+
+        // reconnect
+        let args = Opt::from_iter_safe(&[name, "--reconnect", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                reconnect: true,
+                ..default.clone()
+            }
+        );
+
+        // wait
+        let args = Opt::from_iter_safe(&[name, "--wait", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                wait: Some(Vec::new()),
+                ..default.clone()
+            }
+        );
+        let args = Opt::from_iter_safe(&[name, "--wait=30s", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                wait: Some(vec![Duration::from_secs(30)]),
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--wait=30s", "--wait=5s", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "--wait=forever", default_port]).unwrap_err();
+
+        // output
+        for (arg, mode) in &[
+            ("visualized", OutputMode::Visualized),
+            ("raw", OutputMode::Raw),
+            ("hex", OutputMode::Hex),
+            ("auto", OutputMode::Auto),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "-o", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    output: *mode,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "-o", "text", default_port]).unwrap_err();
+        // -r and --raw are kept for -o raw, and the last one wins
+        for (args, mode) in &[
+            (&[name, "-r", default_port][..], OutputMode::Raw),
+            (&[name, "-o", "hex", "--raw", default_port], OutputMode::Raw),
+            (&[name, "-r", "-o", "hex", default_port], OutputMode::Hex),
+        ] {
+            let args = parse(*args).unwrap();
+            assert_eq!(args.output, *mode);
+        }
+
+        // binary threshold
+        let args = Opt::from_iter_safe(&[name, "--binary-threshold", "8", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                binary_threshold: 8,
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--binary-threshold", "-1", default_port]).unwrap_err();
+
+        // control character style
+        for (arg, style) in &[
+            ("caret", CtrlStyle::Caret),
+            ("escape", CtrlStyle::Escape),
+            ("picture", CtrlStyle::Picture),
+            ("mnemonic", CtrlStyle::Mnemonic),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "--ctrl-style", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    ctrl_style: *style,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "--ctrl-style", "hex", default_port]).unwrap_err();
+
+        // no color
+        let args = Opt::from_iter_safe(&[name, "--no-color", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                no_color: true,
+                ..default.clone()
+            }
+        );
+
+        // pass through
+        let args =
+            Opt::from_iter_safe(&[name, "--pass-through", "LF,CR,HT", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                pass_through: "LF,CR,HT".parse().unwrap(),
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--pass-through", "LF,TAB", default_port]).unwrap_err();
+
+        // ansi
+        for (arg, mode) in &[
+            ("visualize", AnsiMode::Visualize),
+            ("pass", AnsiMode::Pass),
+            ("strip", AnsiMode::Strip),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "--ansi", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    ansi: *mode,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "--ansi", "color", default_port]).unwrap_err();
+
+        // encoding
+        for (arg, encoding) in &[
+            ("utf-8", Encoding::Utf8),
+            ("shift_jis", Encoding::ShiftJis),
+            ("euc-jp", Encoding::EucJp),
+            ("latin1", Encoding::Latin1),
+            ("cp437", Encoding::Cp437),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "--encoding", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    encoding: *encoding,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "--encoding", "utf-16", default_port]).unwrap_err();
+
         // escape quit
-        let args = Opt::from_iter_safe(&[&name, "-e", &default_port]).unwrap();
+        let args = Opt::from_iter_safe(&[name, "-e", default_port]).unwrap();
         assert_eq!(
             args,
             Opt {
@@ -260,5 +1188,496 @@ mod tests {
                 ..default.clone()
             }
         );
+
+        // escape char
+        for (arg, key) in &[("C-a", 0x01), ("C-T", 0x14), ("^t", 0x14), ("^]", 0x1d)] {
+            let args = Opt::from_iter_safe(&[name, "--escape-char", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    escape_char: *key,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "--escape-char", "t", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "--escape-char", "C-", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "--escape-char", "C-tt", default_port]).unwrap_err();
+
+        // control socket
+        let args = Opt::from_iter_safe(&[name, "--control", "/tmp/sc.sock", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                control: Some("/tmp/sc.sock".to_owned()),
+                ..default.clone()
+            }
+        );
+
+        // log
+        let args = Opt::from_iter_safe(&[
+            name,
+            "--log",
+            "sc.log",
+            "--log-tx",
+            "--log-append",
+            "--log-rotate-size",
+            "10M",
+            "--log-rotate-interval",
+            "12h",
+            "--log-keep",
+            "3",
+            default_port,
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                log: Some("sc.log".to_owned()),
+                log_tx: true,
+                log_append: true,
+                log_rotate_size: Some(10 << 20),
+                log_rotate_interval: Some(Duration::from_secs(12 * 3600)),
+                log_keep: 3,
+                ..default.clone()
+            }
+        );
+        for (arg, size) in &[("4096", 4096), ("64k", 64 << 10), ("1G", 1 << 30)] {
+            assert_eq!(size_from_str(arg).unwrap(), *size);
+        }
+        for arg in &["", "0", "1T", "-1", "1.5M", "99999999999G"] {
+            size_from_str(arg).unwrap_err();
+        }
+        for (arg, secs) in &[("90", 90), ("90s", 90), ("30m", 1800), ("1d", 86400)] {
+            assert_eq!(duration_from_str(arg).unwrap(), Duration::from_secs(*secs));
+        }
+        assert_eq!(
+            duration_from_str("250ms").unwrap(),
+            Duration::from_millis(250)
+        );
+        for arg in &["", "0s", "0ms", "1w", "m", "1.5s"] {
+            duration_from_str(arg).unwrap_err();
+        }
+
+        // script
+        let args = Opt::from_iter_safe(&[name, "--script", "boot.sc", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                script: Some("boot.sc".to_owned()),
+                ..default.clone()
+            }
+        );
+
+        // exit on and fail on
+        let args = Opt::from_iter_safe(&[
+            name,
+            "--exit-on",
+            "PASS",
+            "--exit-on",
+            "3:^ERROR",
+            "--fail-on",
+            "FAIL",
+            "--timeout",
+            "5m",
+            default_port,
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                exit_on: vec![
+                    ExitPattern::new(0, "PASS").unwrap(),
+                    ExitPattern::new(3, "^ERROR").unwrap()
+                ],
+                fail_on: vec![ExitPattern::new(1, "FAIL").unwrap()],
+                timeout: Some(Duration::from_secs(300)),
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--exit-on", "(", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "--fail-on", "[", default_port]).unwrap_err();
+        Opt::from_iter_safe(&[name, "--timeout", "0", default_port]).unwrap_err();
+
+        // capture
+        let args = Opt::from_iter_safe(&[name, "--capture", "bug.cap", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                capture: Some("bug.cap".to_owned()),
+                ..default.clone()
+            }
+        );
+
+        // pcap
+        let args = Opt::from_iter_safe(&[
+            name,
+            "--pcap",
+            "modbus.pcapng",
+            "--pcap-link-type",
+            "148",
+            "--pcap-split-idle",
+            "5",
+            default_port,
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                pcap: Some("modbus.pcapng".to_owned()),
+                pcap_link_type: 148,
+                pcap_split_idle: Some(5),
+                ..default.clone()
+            }
+        );
+
+        // replay
+        let args = Opt::from_iter_safe(&[name, "-o", "hex", "replay", "bug.cap"]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                port: None,
+                output: OutputMode::Hex,
+                command: Some(Subcommand::Replay {
+                    file: "bug.cap".to_owned(),
+                    realtime: false,
+                    sent: false,
+                }),
+                ..default.clone()
+            }
+        );
+        let args =
+            Opt::from_iter_safe(&[name, "replay", "--realtime", "--sent", "bug.cap"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Subcommand::Replay {
+                file: "bug.cap".to_owned(),
+                realtime: true,
+                sent: true,
+            })
+        );
+        Opt::from_iter_safe(&[name, "replay"]).unwrap_err();
+        let e = parse_args_from(&[name], || unreachable!()).unwrap_err();
+        assert_eq!(
+            e.downcast::<clap::Error>().unwrap().kind,
+            clap::ErrorKind::MissingRequiredArgument
+        );
+
+        // omap
+        let args = Opt::from_iter_safe(&[name, "--omap", "lfcrlf,delbs", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                omap: OutputMap {
+                    lf: Some(b"\r\n"),
+                    del_bs: true,
+                    ..OutputMap::default()
+                },
+                ..default.clone()
+            }
+        );
+        Opt::from_iter_safe(&[name, "--omap", "lfcr,ignlf", default_port]).unwrap_err();
+
+        // imap
+        for (arg, map) in &[
+            ("none", InputMap::None),
+            ("crlf", InputMap::CrLf),
+            ("cr", InputMap::Cr),
+            ("ignore-cr", InputMap::IgnoreCr),
+            ("lfcr", InputMap::LfCr),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "--imap", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    imap: *map,
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "--imap", "lf", default_port]).unwrap_err();
+
+        // idle
+        let args = Opt::from_iter_safe(&[name, "--idle", "500", default_port]).unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                idle: 500,
+                ..default.clone()
+            }
+        );
+        let args = Opt::from_iter_safe(&[name, "--hex-idle", "500", default_port]).unwrap();
+        assert_eq!(args.idle, 500);
+
+        // timestamp
+        for (arg, mode) in &[
+            ("wall", TimestampMode::Wall),
+            ("monotonic", TimestampMode::Monotonic),
+            ("delta", TimestampMode::Delta),
+        ] {
+            let args = Opt::from_iter_safe(&[name, "-t", arg, default_port]).unwrap();
+            assert_eq!(
+                args,
+                Opt {
+                    timestamp: Some(*mode),
+                    ..default.clone()
+                }
+            );
+        }
+        Opt::from_iter_safe(&[name, "-t", "utc", default_port]).unwrap_err();
+    }
+
+    #[test]
+    fn profiles() {
+        let name = "sc";
+        let config = Config::parse(
+            r#"
+            [profile.nucleo]
+            port = "usb:0483:374b"
+            baud-rate = 921600
+            flow_control = "hardware"
+            reconnect = true
+            exit-on = ["PASS"]
+            wait = "10s"
+
+            [profile.bench]
+            baud-rate = 115200
+            no-color = true
+
+            [profile.typo]
+            baud-rate = "fast"
+            "#,
+        )
+        .unwrap();
+        let parse = |args: &[&str]| parse_args_from(args, || Ok(config.clone()));
+        let usage_error = |args: &[&str]| parse(args).unwrap_err().downcast::<clap::Error>();
+
+        let nucleo = Opt::from_iter_safe(&[
+            name,
+            "-b",
+            "921600",
+            "-f",
+            "hardware",
+            "--reconnect",
+            "--exit-on",
+            "PASS",
+            "--wait=10s",
+            "usb:0483:374b",
+        ])
+        .unwrap();
+        assert_eq!(parse(&[name, "@nucleo"]).unwrap(), nucleo);
+        assert_eq!(
+            parse(&[name, "--profile", "nucleo"]).unwrap(),
+            Opt {
+                profile: Some("nucleo".to_owned()),
+                ..nucleo.clone()
+            }
+        );
+
+        // The command line overrides the profile, including lists
+        let args = parse(&[
+            name,
+            "-b",
+            "115200",
+            "-f",
+            "none",
+            "--exit-on",
+            "DONE",
+            "--wait=1s",
+            "--profile",
+            "nucleo",
+            "/dev/ttyUSB0",
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            Opt {
+                port: Some("/dev/ttyUSB0".to_owned()),
+                profile: Some("nucleo".to_owned()),
+                baud_rate: 115_200,
+                flow_control: FlowControl::None,
+                exit_on: vec![ExitPattern::new(0, "DONE").unwrap()],
+                wait: Some(vec![Duration::from_secs(1)]),
+                ..nucleo.clone()
+            }
+        );
+        let args = parse(&[name, "--fail-on", "FAIL", "--wait", "@nucleo"]).unwrap();
+        assert_eq!(
+            (args.exit_on, args.fail_on, args.wait),
+            (
+                nucleo.exit_on.clone(),
+                vec![ExitPattern::new(1, "FAIL").unwrap()],
+                Some(Vec::new())
+            )
+        );
+
+        // Flags set by the profile are turned off
+        assert!(
+            !parse(&[name, "@nucleo", "--no-reconnect"])
+                .unwrap()
+                .reconnect
+        );
+        let args = parse(&[name, "--profile", "bench", "--color", "/dev/ttyACM0"]).unwrap();
+        assert!(!args.no_color);
+        assert!(
+            parse(&[name, "--no-reconnect", "--reconnect", "@nucleo"])
+                .unwrap()
+                .reconnect
+        );
+        for &(negation, flag) in NEGATED_FLAGS {
+            let flag = format!("--{}", flag);
+            let negation = format!("--{}", negation);
+            assert_eq!(
+                parse(&[name, &flag, &negation, "/dev/ttyACM0"]).unwrap(),
+                parse(&[name, "/dev/ttyACM0"]).unwrap(),
+                "{}",
+                negation
+            );
+        }
+
+        // A profile without a port
+        let args = parse(&[name, "--profile", "bench", "/dev/ttyACM0"]).unwrap();
+        assert_eq!((args.baud_rate, args.no_color), (115_200, true));
+        assert_eq!(
+            usage_error(&[name, "@bench"]).unwrap().kind,
+            clap::ErrorKind::MissingRequiredArgument
+        );
+        assert!(parse(&[name, "@bench", "--list"]).unwrap().list);
+
+        usage_error(&[name, "--profile", "bench", "@nucleo"]).unwrap();
+        usage_error(&[name, "@nucleo", "--baud-rate", "fast"]).unwrap();
+        let e = usage_error(&[name, "@typo"]).unwrap_err();
+        assert!(e.to_string().starts_with("Invalid profile: typo: "));
+        let e = usage_error(&[name, "@nrf52"]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Unknown profile: nrf52 (defined: bench, nucleo, typo)"
+        );
+
+        // Configuration files are not read without a profile
+        parse_args_from(&[name, "/dev/ttyACM0"], || unreachable!()).unwrap();
+    }
+
+    #[test]
+    fn lines() {
+        let name = "sc";
+        for (arg, baud_rate, frame) in &[
+            (
+                "115200,8N1",
+                Some(115_200),
+                Some((DataBits::Eight, Parity::None, StopBits::One)),
+            ),
+            (
+                "9600:7e2",
+                Some(9600),
+                Some((DataBits::Seven, Parity::Even, StopBits::Two)),
+            ),
+            (
+                "300 5O1",
+                Some(300),
+                Some((DataBits::Five, Parity::Odd, StopBits::One)),
+            ),
+            (
+                "250000,8M2",
+                Some(250_000),
+                Some((DataBits::Eight, Parity::Mark, StopBits::Two)),
+            ),
+            (
+                "5s1.5",
+                None,
+                Some((DataBits::Five, Parity::Space, StopBits::OnePointFive)),
+            ),
+            ("921600", Some(921_600), None),
+            (
+                "6N2",
+                None,
+                Some((DataBits::Six, Parity::None, StopBits::Two)),
+            ),
+        ] {
+            let line = arg.parse::<Line>().unwrap();
+            assert_eq!(
+                (line.baud_rate, line.frame),
+                (*baud_rate, *frame),
+                "{}",
+                arg
+            );
+        }
+        for (arg, msg) in &[
+            ("", "Invalid baud rate in : "),
+            ("0,8N1", "Invalid baud rate in 0,8N1: 0"),
+            ("fast,8N1", "Invalid baud rate in fast,8N1: fast"),
+            ("115200,", "Invalid frame in 115200,: Frame must be data bits, parity and stop bits such as 8N1: "),
+            ("9N1", "Invalid frame in 9N1: Unknown data bits: 9"),
+            ("115200,8X1", "Invalid frame in 115200,8X1: Unknown parity: X (N, O, E, M or S)"),
+            ("8N3", "Invalid frame in 8N3: Unknown stop bits: 3"),
+            ("8N1.5", "Invalid frame in 8N1.5: 1.5 stop bits need 5 data bits: 8N1.5"),
+            ("8N", "Invalid frame in 8N: Frame must be data bits, parity and stop bits such as 8N1: 8N"),
+            ("8N1,115200", "Invalid baud rate in 8N1,115200: 8N1"),
+        ] {
+            let e = arg.parse::<Line>().unwrap_err();
+            assert_eq!(format!("{:#}", e), *msg);
+        }
+
+        let parse = |args: &[&str]| parse_args_from(args, || unreachable!());
+        let line = |args: &Opt| (args.baud_rate, args.data_bits, args.parity, args.stop_bits);
+
+        // --line overrides the options given before, and is overridden by the options after
+        let args = parse(&[
+            name,
+            "-p",
+            "odd",
+            "--line",
+            "115200,8E1",
+            "-s",
+            "2",
+            "/dev/ttyUSB0",
+        ])
+        .unwrap();
+        assert_eq!(
+            line(&args),
+            (115_200, DataBits::Eight, Parity::Even, StopBits::Two)
+        );
+        let args = parse(&[name, "--line", "7E1", "-b", "300", "/dev/ttyUSB0"]).unwrap();
+        assert_eq!(
+            line(&args),
+            (300, DataBits::Seven, Parity::Even, StopBits::One)
+        );
+        parse(&[name, "--line", "8Q1", "/dev/ttyUSB0"]).unwrap_err();
+
+        // 1.5 stop bits need 5 data bits, however they are given
+        let args = parse(&[name, "-s", "1.5", "--line", "5N1.5", "/dev/ttyUSB0"]).unwrap();
+        assert_eq!(
+            line(&args),
+            (9600, DataBits::Five, Parity::None, StopBits::OnePointFive)
+        );
+        for args in &[
+            &[name, "-s", "1.5", "/dev/ttyUSB0"][..],
+            &[name, "--line", "5N1.5", "-d", "8", "/dev/ttyUSB0"],
+        ] {
+            let e = parse(args).unwrap_err().downcast::<clap::Error>().unwrap();
+            assert_eq!(e.kind, clap::ErrorKind::ArgumentConflict, "{:?}", args);
+        }
+
+        // PORT@LINE overrides all
+        let args = parse(&[
+            name,
+            "-b",
+            "300",
+            "--line",
+            "9600",
+            "/dev/ttyUSB0@115200:8E1",
+        ])
+        .unwrap();
+        assert_eq!(args.port.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(
+            line(&args),
+            (115_200, DataBits::Eight, Parity::Even, StopBits::One)
+        );
+        let args = parse(&[name, "by-id:*@*"]).unwrap();
+        assert_eq!(args.port.as_deref(), Some("by-id:*@*"));
+        let e = parse(&[name, "/dev/ttyUSB0@115200:8N4"]).unwrap_err();
+        let e = e.downcast::<clap::Error>().unwrap();
+        assert_eq!(e.kind, clap::ErrorKind::InvalidValue);
+        assert!(e.message.ends_with("Invalid line settings of the port: /dev/ttyUSB0@115200:8N4: Invalid frame in 115200:8N4: Unknown stop bits: 4"));
     }
 }
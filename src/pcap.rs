@@ -0,0 +1,313 @@
+//! pcapng files of session traffic
+//!
+//! Each chunk of bytes is written as an Enhanced Packet Block with the direction in `epb_flags`,
+//! so that tools such as Wireshark can dissect serial protocols with a user link type.
+
+use crate::{capture::Direction, timestamp::ReadTime};
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// `LINKTYPE_USER0`, the first link type reserved for private use.
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Bytes merged into a packet.
+#[derive(Debug)]
+struct Packet {
+    direction: Direction,
+    /// Time of the first chunk, which is the timestamp of the packet.
+    first: Instant,
+    /// Time of the last chunk, to find an idle gap.
+    last: Instant,
+    bytes: Vec<u8>,
+}
+
+/// Writer of a pcapng file with a single interface.
+///
+/// Without `split_idle`, each chunk is written as a packet. With it, chunks in the same direction
+/// are merged into a packet until the direction changes or no chunk comes for the time.
+/// The merged packet is held until then, so call [flush](#method.flush) when idle.
+///
+/// ```
+///     # use serialcat::{capture::Direction, pcap::{PcapWriter, LINKTYPE_USER0}};
+///     # use serialcat::timestamp::ReadTime;
+///
+///     let start = ReadTime::now();
+///     let mut pcap = PcapWriter::new(Vec::new(), LINKTYPE_USER0, "/dev/ttyACM0", start, None)
+///         .unwrap();
+///     pcap.write(Direction::Sent, b"\x01\x03\x00\x00\x00\x01\x84\x0a", start.instant)
+///         .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    start: ReadTime,
+    split_idle: Option<Duration>,
+    packet: Option<Packet>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Start a pcapng file at `start` by writing the section header and the interface named
+    /// `name`.
+    pub fn new(
+        writer: W,
+        link_type: u16,
+        name: &str,
+        start: ReadTime,
+        split_idle: Option<Duration>,
+    ) -> io::Result<PcapWriter<W>> {
+        let mut pcap = PcapWriter {
+            writer,
+            start,
+            split_idle,
+            packet: None,
+            buffer: Vec::new(),
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes()); // Major version
+        body.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        body.extend_from_slice(&(-1i64).to_le_bytes()); // Unknown section length
+        pcap.write_block(SECTION_HEADER_BLOCK, &body)?;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&link_type.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+        body.extend_from_slice(&0u32.to_le_bytes()); // No snap length
+        put_option(&mut body, OPT_IF_NAME, name.as_bytes());
+        put_option(&mut body, OPT_ENDOFOPT, b"");
+        pcap.write_block(INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        Ok(pcap)
+    }
+
+    /// Write the bytes received or sent at `time`.
+    pub fn write(&mut self, direction: Direction, bytes: &[u8], time: Instant) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        let split_idle = match self.split_idle {
+            Some(split_idle) => split_idle,
+            None => return self.write_packet(direction, time, bytes),
+        };
+
+        if let Some(packet) = &mut self.packet {
+            if packet.direction == direction
+                && time.saturating_duration_since(packet.last) < split_idle
+            {
+                packet.bytes.extend_from_slice(bytes);
+                packet.last = time;
+                return Ok(());
+            }
+        }
+        self.flush()?;
+        self.packet = Some(Packet {
+            direction,
+            first: time,
+            last: time,
+            bytes: bytes.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Whether a merged packet is held.
+    pub fn pending(&self) -> bool {
+        self.packet.is_some()
+    }
+
+    /// Time to wait for more chunks of the held packet.
+    pub fn split_idle(&self) -> Option<Duration> {
+        self.split_idle
+    }
+
+    /// Write the held packet.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.packet.take() {
+            Some(packet) => self.write_packet(packet.direction, packet.first, &packet.bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn write_packet(
+        &mut self,
+        direction: Direction,
+        time: Instant,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let wall = self.start.wall;
+        let micros = (wall.timestamp() as u64 * 1_000_000 + wall.timestamp_subsec_micros() as u64)
+            + time
+                .saturating_duration_since(self.start.instant)
+                .as_micros() as u64;
+        let flags: u32 = match direction {
+            Direction::Received => 1, // Inbound
+            Direction::Sent => 2,     // Outbound
+        };
+
+        let mut body = Vec::with_capacity(bytes.len() + 32);
+        body.extend_from_slice(&0u32.to_le_bytes()); // Interface ID
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(bytes.len() as u32).to_le_bytes()); // Captured length
+        body.extend_from_slice(&(bytes.len() as u32).to_le_bytes()); // Original length
+        put_padded(&mut body, bytes);
+        put_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        put_option(&mut body, OPT_ENDOFOPT, b"");
+        self.write_block(ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    /// Write a block at once not to leave a half of it.
+    fn write_block(&mut self, kind: u32, body: &[u8]) -> io::Result<()> {
+        let len = (body.len() + 12) as u32;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&kind.to_le_bytes());
+        self.buffer.extend_from_slice(&len.to_le_bytes());
+        self.buffer.extend_from_slice(body);
+        self.buffer.extend_from_slice(&len.to_le_bytes());
+        self.writer.write_all(&self.buffer)?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Drop for PcapWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn put_padded(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.extend_from_slice(bytes);
+    body.resize(body.len() + (4 - bytes.len() % 4) % 4, 0);
+}
+
+fn put_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    put_padded(body, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::convert::TryInto as _;
+
+    fn start() -> ReadTime {
+        ReadTime {
+            instant: Instant::now(),
+            wall: DateTime::parse_from_rfc3339("2020-01-02T03:04:05.678901Z").unwrap(),
+        }
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    /// Split a file into blocks of `(type, body)`, checking the lengths.
+    fn blocks(mut file: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        while !file.is_empty() {
+            let len = u32_at(file, 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(file, len - 4) as usize, len);
+            blocks.push((u32_at(file, 0), &file[8..len - 4]));
+            file = &file[len..];
+        }
+        blocks
+    }
+
+    /// Timestamp in microseconds, flags and data of an Enhanced Packet Block.
+    fn packet(body: &[u8]) -> (u64, u32, &[u8]) {
+        let micros = (u64::from(u32_at(body, 4)) << 32) | u64::from(u32_at(body, 8));
+        let len = u32_at(body, 12) as usize;
+        let options = &body[20 + ((len + 3) & !3)..];
+        assert_eq!(&options[..4], b"\x02\x00\x04\x00");
+        (micros, u32_at(options, 4), &body[20..20 + len])
+    }
+
+    #[test]
+    fn headers() {
+        let pcap = PcapWriter::new(Vec::new(), LINKTYPE_USER0, "/dev/ttyS0", start(), None);
+        let file = pcap.unwrap().writer.clone();
+        let blocks = blocks(&file);
+        assert_eq!(
+            blocks,
+            [
+                (
+                    SECTION_HEADER_BLOCK,
+                    &b"\x4d\x3c\x2b\x1a\x01\x00\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff"[..]
+                ),
+                (
+                    INTERFACE_DESCRIPTION_BLOCK,
+                    b"\x93\x00\x00\x00\x00\x00\x00\x00\x02\x00\x0a\x00/dev/ttyS0\x00\x00\x00\x00\x00\x00"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn packets() {
+        let start = start();
+        let at = |millis| start.instant + Duration::from_millis(millis);
+        let epoch = 1_577_934_245_678_901;
+
+        let mut pcap = PcapWriter::new(Vec::new(), LINKTYPE_USER0, "tty", start, None).unwrap();
+        pcap.write(Direction::Sent, b"ping", at(0)).unwrap();
+        pcap.write(Direction::Received, b"", at(5)).unwrap();
+        pcap.write(Direction::Received, b"pong\r\n", at(10))
+            .unwrap();
+        assert!(!pcap.pending());
+
+        let file = pcap.writer.clone();
+        let blocks = blocks(&file);
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[2..].iter().all(|b| b.0 == ENHANCED_PACKET_BLOCK));
+        assert_eq!(packet(blocks[2].1), (epoch, 2, &b"ping"[..]));
+        assert_eq!(packet(blocks[3].1), (epoch + 10_000, 1, &b"pong\r\n"[..]));
+    }
+
+    #[test]
+    fn split_idle() {
+        let start = start();
+        let at = |millis| start.instant + Duration::from_millis(millis);
+        let epoch = 1_577_934_245_678_901;
+        let idle = Some(Duration::from_millis(4));
+
+        let mut pcap = PcapWriter::new(Vec::new(), LINKTYPE_USER0, "tty", start, idle).unwrap();
+        pcap.write(Direction::Sent, b"\x01\x03", at(0)).unwrap();
+        pcap.write(Direction::Sent, b"\x00\x00", at(1)).unwrap();
+        pcap.write(Direction::Received, b"\x01", at(3)).unwrap();
+        pcap.write(Direction::Received, b"\x03", at(6)).unwrap();
+        pcap.write(Direction::Received, b"\x02", at(10)).unwrap();
+        assert!(pcap.pending());
+        pcap.flush().unwrap();
+        assert!(!pcap.pending());
+
+        let file = pcap.writer.clone();
+        let packets = blocks(&file)[2..]
+            .iter()
+            .map(|b| packet(b.1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            packets,
+            [
+                (epoch, 2, &b"\x01\x03\x00\x00"[..]),
+                (epoch + 3_000, 1, b"\x01\x03"),
+                (epoch + 10_000, 1, b"\x02"),
+            ]
+        );
+    }
+}
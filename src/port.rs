@@ -0,0 +1,504 @@
+//! Serial port control
+
+use anyhow::{anyhow, Context as _, Result};
+use serialport::{posix::TTYPort, DataBits, FlowControl, SerialPort as _, SerialPortSettings};
+use std::{
+    fmt, io, mem,
+    os::unix::io::{AsRawFd, FromRawFd as _},
+    thread,
+    time::Duration,
+};
+use tokio_serial::Serial;
+
+/// Parity of serial port.
+///
+/// Unlike `serialport`, this includes mark and space parity, where the parity bit is always 1 or 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+/// Stop bits of serial port.
+///
+/// Unlike `serialport`, this includes 1.5 stop bits. UARTs send 1.5 stop bits instead of 2 with 5
+/// data bits, so termios has the same setting for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    OnePointFive,
+    Two,
+}
+
+/// Line settings of serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            baud_rate: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+/// Level of a modem line to set right after the serial port is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineLevel {
+    On,
+    Off,
+    /// Leave the level as the driver set it on open.
+    Keep,
+}
+
+/// Levels of DTR and RTS to set right after the serial port is opened, or `None` for the driver
+/// default.
+///
+/// The driver raises DTR and RTS on open, and drops them on close. Boards wired to reset by them,
+/// such as Arduino, reset at the edges. The lines are set only after the open has raised them, so
+/// `Off` still makes an edge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModemLines {
+    pub dtr: Option<LineLevel>,
+    pub rts: Option<LineLevel>,
+    /// Clear HUPCL so that the driver keeps the lines on close, and the next open has no edge.
+    /// HUPCL stays cleared on the device after exit, for every program using it.
+    pub no_hupcl: bool,
+}
+
+/// Sequence of DTR and RTS to reset a board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetPulse {
+    /// Drop DTR, and raise it for 100 ms, for Arduino style boards.
+    Dtr,
+    /// Drop RTS, and raise it for 100 ms.
+    Rts,
+    /// Pull EN of ESP32 by RTS with DTR dropped, to run the firmware.
+    Esp32,
+    /// Pull EN by RTS and then IO0 by DTR, to enter the ROM bootloader of ESP32 as esptool does.
+    Esp32Boot,
+}
+
+impl fmt::Display for ResetPulse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ResetPulse::Dtr => "dtr",
+            ResetPulse::Rts => "rts",
+            ResetPulse::Esp32 => "esp32",
+            ResetPulse::Esp32Boot => "esp32-boot",
+        })
+    }
+}
+
+impl ResetPulse {
+    /// Steps of levels of DTR and RTS, or `None` to leave the line, and the time to wait after each.
+    fn steps(self) -> &'static [(Option<bool>, Option<bool>, u64)] {
+        match self {
+            ResetPulse::Dtr => &[
+                (Some(false), None, 50),
+                (Some(true), None, 100),
+                (Some(false), None, 0),
+            ],
+            ResetPulse::Rts => &[
+                (None, Some(false), 50),
+                (None, Some(true), 100),
+                (None, Some(false), 0),
+            ],
+            ResetPulse::Esp32 => &[(Some(false), Some(true), 100), (None, Some(false), 0)],
+            ResetPulse::Esp32Boot => &[
+                (Some(false), Some(true), 100),
+                (Some(true), Some(false), 50),
+                (Some(false), None, 0),
+            ],
+        }
+    }
+}
+
+/// Open the serial port with the settings, and set the modem lines after the driver raised them.
+///
+/// The baud rate is not checked. Compare it with [Control::settings](struct.Control.html#method.settings)
+/// to know the actual baud rate the driver rounded to.
+pub fn open(path: &str, settings: &Settings, lines: &ModemLines) -> Result<Serial> {
+    // Open without mark/space parity and 1.5 stop bits, which serialport does not support, and
+    // set them after opening
+    let serial_port_settings = SerialPortSettings {
+        baud_rate: settings.baud_rate,
+        data_bits: settings.data_bits,
+        flow_control: settings.flow_control,
+        parity: serialport::Parity::None,
+        stop_bits: serialport::StopBits::One,
+        timeout: Duration::from_millis(50),
+    };
+    let serial = Serial::from_path(path, &serial_port_settings)?;
+    let mut control = Control::new(&serial)?;
+    control.set_parity(settings.parity)?;
+    control.set_stop_bits(settings.stop_bits)?;
+    control.set_modem_lines(lines)?;
+    Ok(serial)
+}
+
+/// Handle to control an opened serial port.
+///
+/// While [Serial](../../tokio_serial/struct.Serial.html) is split into reader and writer, this
+/// handle changes settings and modem lines of the same port through a duplicated file descriptor.
+pub struct Control {
+    /// `None` after closed.
+    port: Option<TTYPort>,
+}
+
+impl Control {
+    /// Create a handle from an opened port.
+    pub fn new<P>(port: &P) -> Result<Control>
+    where
+        P: AsRawFd,
+    {
+        let fd = unsafe { libc::dup(port.as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Cannot duplicate serial port");
+        }
+        let port = unsafe { TTYPort::from_raw_fd(fd) };
+        Ok(Control { port: Some(port) })
+    }
+
+    /// Close the duplicated file descriptor, such as when the port disappeared.
+    /// Methods fail after closed.
+    pub fn close(&mut self) {
+        self.port = None;
+    }
+
+    fn port(&self) -> Result<&TTYPort> {
+        self.port
+            .as_ref()
+            .ok_or_else(|| anyhow!("Serial port is disconnected"))
+    }
+
+    fn port_mut(&mut self) -> Result<&mut TTYPort> {
+        self.port
+            .as_mut()
+            .ok_or_else(|| anyhow!("Serial port is disconnected"))
+    }
+
+    fn termios(&self) -> Result<libc::termios2> {
+        let mut termios = unsafe { mem::zeroed::<libc::termios2>() };
+        if unsafe { libc::ioctl(self.port()?.as_raw_fd(), libc::TCGETS2, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error()).context("Cannot get line settings");
+        }
+        Ok(termios)
+    }
+
+    // termios2 keeps a baud rate not in the Bxxx constants, set with BOTHER
+    fn set_termios(&mut self, termios: &libc::termios2) -> Result<()> {
+        if unsafe { libc::ioctl(self.port_mut()?.as_raw_fd(), libc::TCSETS2, termios) } != 0 {
+            return Err(io::Error::last_os_error()).context("Cannot set line settings");
+        }
+        Ok(())
+    }
+
+    /// Current settings of the port.
+    ///
+    /// The baud rate is the actual one the driver uses, which may be rounded from the one set.
+    pub fn settings(&self) -> Result<Settings> {
+        let port = self.port()?;
+        let termios = self.termios()?;
+        let parity = match termios.c_cflag & (libc::PARENB | libc::PARODD | libc::CMSPAR) {
+            c if c & libc::PARENB == 0 => Parity::None,
+            c if c & libc::CMSPAR == 0 && c & libc::PARODD == 0 => Parity::Even,
+            c if c & libc::CMSPAR == 0 => Parity::Odd,
+            c if c & libc::PARODD == 0 => Parity::Space,
+            _ => Parity::Mark,
+        };
+        let data_bits = port.data_bits()?;
+        let stop_bits = match (termios.c_cflag & libc::CSTOPB != 0, data_bits) {
+            (false, _) => StopBits::One,
+            (true, DataBits::Five) => StopBits::OnePointFive,
+            (true, _) => StopBits::Two,
+        };
+        Ok(Settings {
+            baud_rate: port.baud_rate()?,
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control: port.flow_control()?,
+        })
+    }
+
+    /// Change the baud rate, even to one not standard such as 250000.
+    ///
+    /// The driver may round it, as [settings](#method.settings) tells.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.port_mut()?
+            .set_baud_rate(baud_rate)
+            .with_context(|| format!("Cannot set baud rate: {}", baud_rate))
+    }
+
+    /// Change the data bits.
+    pub fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
+        self.port_mut()?
+            .set_data_bits(data_bits)
+            .context("Cannot set data bits")
+    }
+
+    /// Change the parity.
+    pub fn set_parity(&mut self, parity: Parity) -> Result<()> {
+        let mut termios = self.termios().context("Cannot set parity")?;
+        let (cflag, check) = match parity {
+            Parity::None => (0, false),
+            Parity::Odd => (libc::PARENB | libc::PARODD, true),
+            Parity::Even => (libc::PARENB, true),
+            Parity::Mark => (libc::PARENB | libc::PARODD | libc::CMSPAR, true),
+            Parity::Space => (libc::PARENB | libc::CMSPAR, true),
+        };
+        termios.c_cflag &= !(libc::PARENB | libc::PARODD | libc::CMSPAR);
+        termios.c_cflag |= cflag;
+        // Check parity of received bytes as serialport does
+        if check {
+            termios.c_iflag |= libc::INPCK;
+            termios.c_iflag &= !libc::IGNPAR;
+        } else {
+            termios.c_iflag &= !libc::INPCK;
+            termios.c_iflag |= libc::IGNPAR;
+        }
+        self.set_termios(&termios).context("Cannot set parity")
+    }
+
+    /// Change the stop bits.
+    ///
+    /// 1.5 stop bits need 5 data bits, while 2 stop bits turn into 1.5 with 5 data bits.
+    pub fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()> {
+        let mut termios = self.termios().context("Cannot set stop bits")?;
+        let five = termios.c_cflag & libc::CSIZE == libc::CS5;
+        match stop_bits {
+            StopBits::One => termios.c_cflag &= !libc::CSTOPB,
+            StopBits::OnePointFive if !five => {
+                return Err(anyhow!("1.5 stop bits need 5 data bits"))
+                    .context("Cannot set stop bits")
+            }
+            StopBits::OnePointFive | StopBits::Two => termios.c_cflag |= libc::CSTOPB,
+        }
+        self.set_termios(&termios).context("Cannot set stop bits")
+    }
+
+    /// Change the flow control.
+    pub fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()> {
+        self.port_mut()?
+            .set_flow_control(flow_control)
+            .context("Cannot set flow control")
+    }
+
+    fn modem_lines(&self) -> Result<libc::c_int> {
+        let mut lines = 0;
+        if unsafe { libc::ioctl(self.port()?.as_raw_fd(), libc::TIOCMGET, &mut lines) } != 0 {
+            return Err(io::Error::last_os_error()).context("Cannot get modem lines");
+        }
+        Ok(lines)
+    }
+
+    /// Level of DTR line.
+    pub fn dtr(&self) -> Result<bool> {
+        Ok(self.modem_lines()? & libc::TIOCM_DTR != 0)
+    }
+
+    /// Level of RTS line.
+    pub fn rts(&self) -> Result<bool> {
+        Ok(self.modem_lines()? & libc::TIOCM_RTS != 0)
+    }
+
+    /// Set DTR line.
+    pub fn set_dtr(&mut self, level: bool) -> Result<()> {
+        self.port_mut()?
+            .write_data_terminal_ready(level)
+            .context("Cannot set DTR")
+    }
+
+    /// Set RTS line.
+    pub fn set_rts(&mut self, level: bool) -> Result<()> {
+        self.port_mut()?
+            .write_request_to_send(level)
+            .context("Cannot set RTS")
+    }
+
+    /// Set DTR and RTS to the levels to open the port with.
+    pub fn set_modem_lines(&mut self, lines: &ModemLines) -> Result<()> {
+        match lines.dtr {
+            Some(LineLevel::On) => self.set_dtr(true)?,
+            Some(LineLevel::Off) => self.set_dtr(false)?,
+            Some(LineLevel::Keep) | None => {}
+        }
+        match lines.rts {
+            Some(LineLevel::On) => self.set_rts(true)?,
+            Some(LineLevel::Off) => self.set_rts(false)?,
+            Some(LineLevel::Keep) | None => {}
+        }
+        if lines.no_hupcl {
+            let mut termios = self.termios().context("Cannot clear HUPCL")?;
+            termios.c_cflag &= !libc::HUPCL;
+            self.set_termios(&termios).context("Cannot clear HUPCL")?;
+        }
+        Ok(())
+    }
+
+    /// Reset the board by the sequence of DTR and RTS. This blocks while resetting.
+    pub fn reset(&mut self, pulse: ResetPulse) -> Result<()> {
+        for &(dtr, rts, wait) in pulse.steps() {
+            if let Some(level) = dtr {
+                self.set_dtr(level)?;
+            }
+            if let Some(level) = rts {
+                self.set_rts(level)?;
+            }
+            thread::sleep(Duration::from_millis(wait));
+        }
+        Ok(())
+    }
+
+    /// Send BREAK. This blocks while sending.
+    pub fn send_break(&self) -> Result<()> {
+        if unsafe { libc::tcsendbreak(self.port()?.as_raw_fd(), 0) } != 0 {
+            return Err(io::Error::last_os_error()).context("Cannot send BREAK");
+        }
+        Ok(())
+    }
+}
+
+/// Describe line settings in short, such as `115200 8N1`.
+pub fn describe(settings: &Settings) -> String {
+    let data_bits = match settings.data_bits {
+        DataBits::Five => '5',
+        DataBits::Six => '6',
+        DataBits::Seven => '7',
+        DataBits::Eight => '8',
+    };
+    let parity = match settings.parity {
+        Parity::None => 'N',
+        Parity::Odd => 'O',
+        Parity::Even => 'E',
+        Parity::Mark => 'M',
+        Parity::Space => 'S',
+    };
+    let stop_bits = match settings.stop_bits {
+        StopBits::One => "1",
+        StopBits::OnePointFive => "1.5",
+        StopBits::Two => "2",
+    };
+    let flow_control = match settings.flow_control {
+        FlowControl::None => "",
+        FlowControl::Software => " XON/XOFF",
+        FlowControl::Hardware => " RTS/CTS",
+    };
+    format!(
+        "{} {}{}{}{}",
+        settings.baud_rate, data_bits, parity, stop_bits, flow_control
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control() {
+        let (_master, slave) = TTYPort::pair().unwrap();
+        let mut control = Control::new(&slave).unwrap();
+
+        control.set_baud_rate(115_200).unwrap();
+        assert_eq!(control.settings().unwrap().baud_rate, 115_200);
+        assert_eq!(slave.baud_rate().unwrap(), 115_200);
+
+        // Pseudo terminals always use 8 data bits without parity
+        control.set_data_bits(DataBits::Seven).unwrap();
+        control.set_parity(Parity::Even).unwrap();
+        control.set_stop_bits(StopBits::Two).unwrap();
+        control.set_flow_control(FlowControl::Hardware).unwrap();
+        assert_eq!(describe(&control.settings().unwrap()), "115200 8N2 RTS/CTS");
+        control.set_parity(Parity::Mark).unwrap();
+        control.set_stop_bits(StopBits::OnePointFive).unwrap_err();
+
+        control
+            .set_modem_lines(&ModemLines {
+                dtr: Some(LineLevel::Keep),
+                rts: None,
+                no_hupcl: false,
+            })
+            .unwrap();
+        control
+            .set_modem_lines(&ModemLines {
+                no_hupcl: true,
+                ..ModemLines::default()
+            })
+            .unwrap();
+        assert_eq!(control.termios().unwrap().c_cflag & libc::HUPCL, 0);
+
+        // Baud rates not standard, such as DMX
+        control.set_baud_rate(250_000).unwrap();
+        assert_eq!(control.settings().unwrap().baud_rate, 250_000);
+    }
+
+    #[test]
+    fn reset_pulses() {
+        // Every pulse ends with the lines released, not to hold the board in reset
+        for &pulse in &[
+            ResetPulse::Dtr,
+            ResetPulse::Rts,
+            ResetPulse::Esp32,
+            ResetPulse::Esp32Boot,
+        ] {
+            let (mut dtr, mut rts) = (None, None);
+            for &(d, r, _) in pulse.steps() {
+                dtr = d.or(dtr);
+                rts = r.or(rts);
+            }
+            assert_ne!(dtr, Some(true), "{}", pulse);
+            assert_ne!(rts, Some(true), "{}", pulse);
+            // Shown as the name given to --reset
+            assert_eq!(
+                crate::opt::reset_pulse_from_str(&pulse.to_string()).unwrap(),
+                pulse
+            );
+        }
+    }
+
+    #[test]
+    fn describe_settings() {
+        let settings = Settings {
+            baud_rate: 115_200,
+            ..Default::default()
+        };
+        assert_eq!(describe(&settings), "115200 8N1");
+
+        let settings = Settings {
+            baud_rate: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Hardware,
+        };
+        assert_eq!(describe(&settings), "9600 7E2 RTS/CTS");
+
+        let settings = Settings {
+            baud_rate: 31_250,
+            parity: Parity::Mark,
+            ..Default::default()
+        };
+        assert_eq!(describe(&settings), "31250 8M1");
+        let settings = Settings {
+            data_bits: DataBits::Five,
+            parity: Parity::Space,
+            stop_bits: StopBits::OnePointFive,
+            ..Default::default()
+        };
+        assert_eq!(describe(&settings), "9600 5S1.5");
+    }
+}
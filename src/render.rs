@@ -0,0 +1,912 @@
+//! Rendering received bytes
+
+use anyhow::{bail, Error, Result};
+use bytes::{BufMut, BytesMut};
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::{
+    encoding::Encoding,
+    util::{GetChars, GetCharsMixin as _},
+};
+
+/// How to display received bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Visualize control characters and invalid UTF-8 sequence.
+    Visualized,
+    /// Write received bytes as is.
+    Raw,
+    /// Hexdump with offset and ASCII.
+    Hex,
+    /// Text, switching to hexdump while non-printable bytes continue.
+    Auto,
+}
+
+impl OutputMode {
+    /// The next mode to cycle through modes.
+    pub fn next(self) -> OutputMode {
+        match self {
+            OutputMode::Visualized => OutputMode::Raw,
+            OutputMode::Raw => OutputMode::Hex,
+            OutputMode::Hex => OutputMode::Auto,
+            OutputMode::Auto => OutputMode::Visualized,
+        }
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputMode> {
+        match s {
+            "visualized" => Ok(OutputMode::Visualized),
+            "raw" => Ok(OutputMode::Raw),
+            "hex" => Ok(OutputMode::Hex),
+            "auto" => Ok(OutputMode::Auto),
+            _ => bail!("Unknown output mode: {}", s),
+        }
+    }
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputMode::Visualized => "visualized",
+            OutputMode::Raw => "raw",
+            OutputMode::Hex => "hex",
+            OutputMode::Auto => "auto",
+        })
+    }
+}
+
+/// Renderer of received bytes to the terminal.
+pub trait Render {
+    /// Render bytes in the input and append them to the output.
+    ///
+    /// Incomplete sequences may be left in the input until more bytes come.
+    fn render(&mut self, input: &mut BytesMut, output: &mut BytesMut);
+
+    /// Whether rendered output is held until more bytes come or the input is idle.
+    fn pending(&self) -> bool {
+        false
+    }
+
+    /// Number of input bytes held in the pending output.
+    fn held(&self) -> usize {
+        0
+    }
+
+    /// Render the held output, called when the input is idle.
+    fn flush(&mut self, _output: &mut BytesMut) {}
+
+    /// Restore the terminal state, such as colors, to show other messages.
+    fn reset(&mut self, _output: &mut BytesMut) {}
+}
+
+/// Notation of control characters and invalid bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtrlStyle {
+    /// Caret notation, such as `^M`. C1 controls are written as `M-^E` like `cat -v`.
+    Caret,
+    /// C-style escapes, such as `\x0d`. C1 controls are written as `\u0085`.
+    Escape,
+    /// Unicode Control Pictures, such as `␍`. C1 controls are written as mnemonics.
+    Picture,
+    /// Mnemonics, such as `<CR>`.
+    Mnemonic,
+}
+
+impl FromStr for CtrlStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<CtrlStyle> {
+        match s {
+            "caret" => Ok(CtrlStyle::Caret),
+            "escape" => Ok(CtrlStyle::Escape),
+            "picture" => Ok(CtrlStyle::Picture),
+            "mnemonic" => Ok(CtrlStyle::Mnemonic),
+            _ => bail!("Unknown control character style: {}", s),
+        }
+    }
+}
+
+const C0_NAMES: [&str; 32] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "HT", "LF", "VT", "FF", "CR",
+    "SO", "SI", "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB", "ESC",
+    "FS", "GS", "RS", "US",
+];
+
+const C1_NAMES: [&str; 32] = [
+    "PAD", "HOP", "BPH", "NBH", "IND", "NEL", "SSA", "ESA", "HTS", "HTJ", "VTS", "PLD", "PLU",
+    "RI", "SS2", "SS3", "DCS", "PU1", "PU2", "STS", "CCH", "MW", "SPA", "EPA", "SOS", "SGCI",
+    "SCI", "CSI", "ST", "OSC", "PM", "APC",
+];
+
+/// Mnemonic of the control character.
+///
+/// ```
+///     # use serialcat::render::ctrl_name;
+///
+///     assert_eq!(ctrl_name('\r'), Some("CR"));
+///     assert_eq!(ctrl_name('\u{9b}'), Some("CSI"));
+///     assert_eq!(ctrl_name('a'), None);
+/// ```
+pub fn ctrl_name(c: char) -> Option<&'static str> {
+    match c as u32 {
+        code @ 0x00..=0x1f => Some(C0_NAMES[code as usize]),
+        0x7f => Some("DEL"),
+        code @ 0x80..=0x9f => Some(C1_NAMES[code as usize - 0x80]),
+        _ => None,
+    }
+}
+
+impl CtrlStyle {
+    fn put_char(self, c: char, output: &mut BytesMut) {
+        let code = c as u32;
+        match self {
+            CtrlStyle::Caret => match code {
+                0x00..=0x1f => output.put_slice(&[b'^', code as u8 + b'@']),
+                0x7f => output.put_slice(b"^?"),
+                _ => output.put_slice(&[b'M', b'-', b'^', (code - 0x80) as u8 + b'@']),
+            },
+            CtrlStyle::Escape if code < 0x80 => {
+                output.put_slice(format!("\\x{:02x}", code).as_bytes())
+            }
+            CtrlStyle::Escape => output.put_slice(format!("\\u{:04x}", code).as_bytes()),
+            CtrlStyle::Picture if code < 0x20 || code == 0x7f => {
+                let picture = if code == 0x7f { 0x2421 } else { 0x2400 + code };
+                let mut b = [0; 4];
+                let picture = std::char::from_u32(picture).unwrap();
+                output.put_slice(picture.encode_utf8(&mut b).as_bytes());
+            }
+            CtrlStyle::Picture | CtrlStyle::Mnemonic => {
+                output.put_slice(format!("<{}>", ctrl_name(c).unwrap()).as_bytes())
+            }
+        }
+    }
+
+    fn put_invalid(self, b: u8, output: &mut BytesMut) {
+        let s = match self {
+            CtrlStyle::Caret | CtrlStyle::Picture => format!("<{:02X}>", b),
+            CtrlStyle::Escape => format!("\\x{:02x}", b),
+            // `<FF>` is form feed
+            CtrlStyle::Mnemonic => format!("<0x{:02X}>", b),
+        };
+        output.put_slice(s.as_bytes());
+    }
+}
+
+/// Set of control characters written as is, by comma separated mnemonics such as `LF,HT`.
+///
+/// ```
+///     # use serialcat::render::CtrlSet;
+///
+///     let set: CtrlSet = "lf,cr".parse().unwrap();
+///     assert!(set.contains('\r'));
+///     assert!(!set.contains('\t'));
+///     assert!("none".parse::<CtrlSet>().unwrap().is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CtrlSet(Vec<char>);
+
+impl CtrlSet {
+    /// Whether the set contains the character.
+    pub fn contains(&self, c: char) -> bool {
+        self.0.contains(&c)
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Default for CtrlSet {
+    /// `LF` and `HT`.
+    fn default() -> CtrlSet {
+        CtrlSet(vec!['\n', '\t'])
+    }
+}
+
+impl FromStr for CtrlSet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<CtrlSet> {
+        let mut set = Vec::new();
+        if s == "none" {
+            return Ok(CtrlSet(set));
+        }
+        for name in s.split(',').filter(|name| !name.is_empty()) {
+            let c = (0..=0x9f).filter_map(std::char::from_u32).find(|&c| {
+                ctrl_name(c)
+                    .map(|n| n.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+            });
+            match c {
+                Some(c) => set.push(c),
+                None => bail!("Unknown control character: {}", name),
+            }
+        }
+        Ok(CtrlSet(set))
+    }
+}
+
+/// Handling of ANSI escape sequences (CSI sequences such as `ESC [ 1 ; 31 m`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiMode {
+    /// Visualize `ESC` as other control characters.
+    Visualize,
+    /// Write well-formed sequences as is.
+    Pass,
+    /// Remove well-formed sequences.
+    Strip,
+}
+
+impl FromStr for AnsiMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AnsiMode> {
+        match s {
+            "visualize" => Ok(AnsiMode::Visualize),
+            "pass" => Ok(AnsiMode::Pass),
+            "strip" => Ok(AnsiMode::Strip),
+            _ => bail!("Unknown ANSI escape sequence mode: {}", s),
+        }
+    }
+}
+
+/// Style of visualized control characters and invalid bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Style {
+    /// Notation of control characters and invalid bytes.
+    pub ctrl: CtrlStyle,
+    /// Write them in reverse video.
+    pub color: bool,
+    /// Control characters written as is.
+    pub pass: CtrlSet,
+    /// Handling of ANSI escape sequences.
+    pub ansi: AnsiMode,
+}
+
+impl Style {
+    /// Whether the character is written as is.
+    pub fn passes(&self, ch: &GetChars) -> bool {
+        match *ch {
+            GetChars::Char(c) => !c.is_control() || self.pass.contains(c),
+            GetChars::Err(_) => false,
+        }
+    }
+}
+
+impl Default for Style {
+    /// Caret notation in reverse video, except `LF` and `HT`.
+    fn default() -> Style {
+        Style {
+            ctrl: CtrlStyle::Caret,
+            color: true,
+            pass: CtrlSet::default(),
+            ansi: AnsiMode::Visualize,
+        }
+    }
+}
+
+/// Settings of rendering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Output mode.
+    pub mode: OutputMode,
+    /// Style of visualized output.
+    pub style: Style,
+    /// Text encoding of received bytes.
+    pub encoding: Encoding,
+    /// Idle time to [flush](trait.Render.html#method.flush) held output.
+    pub idle: Duration,
+    /// Threshold of [Auto](struct.Auto.html).
+    pub binary_threshold: usize,
+}
+
+impl Config {
+    /// Create a renderer of the mode.
+    pub fn renderer(&self) -> Box<dyn Render + Send> {
+        match self.mode {
+            OutputMode::Visualized => Box::new(Visualized::new(self.style.clone(), self.encoding)),
+            OutputMode::Raw => Box::new(Raw),
+            OutputMode::Hex => Box::new(HexDump::new()),
+            OutputMode::Auto => Box::new(Auto::new(
+                self.binary_threshold,
+                self.style.clone(),
+                self.encoding,
+            )),
+        }
+    }
+}
+
+/// Write received bytes as is.
+#[derive(Debug, Clone, Default)]
+pub struct Raw;
+
+impl Render for Raw {
+    fn render(&mut self, input: &mut BytesMut, output: &mut BytesMut) {
+        output.extend_from_slice(&input.split());
+    }
+}
+
+/// Visualize control characters and invalid UTF-8 sequence in the style.
+///
+/// Unless ANSI escape sequences are visualized, an incomplete sequence is held until the rest
+/// comes. If it turns out malformed or the input is idle, it is visualized.
+///
+/// ```
+///     # use serialcat::{encoding::Encoding, render::{Render as _, Style, Visualized}};
+///     # use bytes::BytesMut;
+///
+///     let mut input = BytesMut::from(&b"a\x01\xffb"[..]);
+///     let mut output = BytesMut::new();
+///     Visualized::new(Style::default(), Encoding::Utf8).render(&mut input, &mut output);
+///     assert_eq!(&output[..], b"a\x1b[7m^A<FF>\x1b[mb");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Visualized {
+    style: Style,
+    encoding: Encoding,
+    reversed: bool,
+    /// Incomplete ANSI escape sequence.
+    sequence: String,
+    /// Whether SGR sequences have been written.
+    colored: bool,
+}
+
+/// Maximum length of ANSI escape sequences to hold.
+const MAX_SEQUENCE: usize = 64;
+
+impl Visualized {
+    /// Create a renderer of text in the encoding.
+    pub fn new(style: Style, encoding: Encoding) -> Visualized {
+        Visualized {
+            style,
+            encoding,
+            reversed: false,
+            sequence: String::new(),
+            colored: false,
+        }
+    }
+
+    fn set_reversed(&mut self, output: &mut BytesMut, reversed: bool) {
+        if self.style.color && self.reversed != reversed {
+            output.put_slice(if reversed { b"\x1b[7m" } else { b"\x1b[m" });
+            self.reversed = reversed;
+        }
+    }
+
+    fn put(&mut self, ch: &GetChars, output: &mut BytesMut) {
+        if self.style.ansi == AnsiMode::Visualize || !self.put_sequence(ch, output) {
+            self.put_char(ch, output);
+        }
+    }
+
+    /// Parse the character as a part of ANSI escape sequence, returns `false` if it is not.
+    fn put_sequence(&mut self, ch: &GetChars, output: &mut BytesMut) -> bool {
+        let c = match *ch {
+            GetChars::Char(c) => c,
+            GetChars::Err(_) => {
+                self.abort_sequence(output);
+                return false;
+            }
+        };
+
+        let intermediate = self.sequence.bytes().any(|b| (0x20..=0x2f).contains(&b));
+        match (self.sequence.len(), c) {
+            (0, '\x1b') | (1, '[') => self.sequence.push(c),
+            (0, _) => return false,
+            (n, '\x30'..='\x3f') if n > 1 && !intermediate => self.sequence.push(c),
+            (n, '\x20'..='\x2f') if n > 1 => self.sequence.push(c),
+            (n, '\x40'..='\x7e') if n > 1 => {
+                self.sequence.push(c);
+                let sequence = std::mem::take(&mut self.sequence);
+                if self.style.ansi == AnsiMode::Pass {
+                    self.set_reversed(output, false);
+                    output.put_slice(sequence.as_bytes());
+                    self.colored |= c == 'm';
+                }
+                return true;
+            }
+            _ => {
+                // Malformed, and this character may start another sequence
+                self.abort_sequence(output);
+                return self.put_sequence(ch, output);
+            }
+        }
+
+        if self.sequence.len() > MAX_SEQUENCE {
+            self.abort_sequence(output);
+        }
+        true
+    }
+
+    /// Visualize the incomplete sequence.
+    fn abort_sequence(&mut self, output: &mut BytesMut) {
+        for c in std::mem::take(&mut self.sequence).chars() {
+            self.put_char(&GetChars::Char(c), output);
+        }
+    }
+
+    fn put_char(&mut self, ch: &GetChars, output: &mut BytesMut) {
+        let passes = self.style.passes(ch);
+        self.set_reversed(output, !passes);
+        match *ch {
+            GetChars::Char(c) if passes => {
+                let mut b = [0; 4];
+                output.put_slice(c.encode_utf8(&mut b).as_bytes());
+            }
+            GetChars::Char(c) => self.style.ctrl.put_char(c, output),
+            GetChars::Err(b) => self.style.ctrl.put_invalid(b, output),
+        }
+    }
+}
+
+impl Render for Visualized {
+    fn render(&mut self, input: &mut BytesMut, output: &mut BytesMut) {
+        for ch in input.get_chars_in(self.encoding) {
+            self.put(&ch, output);
+        }
+    }
+
+    fn pending(&self) -> bool {
+        !self.sequence.is_empty()
+    }
+
+    fn held(&self) -> usize {
+        self.sequence.len()
+    }
+
+    fn flush(&mut self, output: &mut BytesMut) {
+        self.abort_sequence(output);
+    }
+
+    fn reset(&mut self, output: &mut BytesMut) {
+        self.abort_sequence(output);
+        // A single SGR reset clears both reverse video and colors passed through
+        if self.reversed || self.colored {
+            output.put_slice(b"\x1b[m");
+            self.reversed = false;
+            self.colored = false;
+        }
+    }
+}
+
+const HEX_ROW: usize = 16;
+
+/// Hexdump with offset and ASCII, such as
+/// `00000000  48 65 6c 6c 6f 0d 0a 00  ff                       |Hello....|`.
+///
+/// A row is written when 16 bytes are received, or a partial row is written when the input is
+/// idle. The next row starts at the offset following the partial row.
+#[derive(Debug, Clone, Default)]
+pub struct HexDump {
+    offset: u64,
+    row: Vec<u8>,
+}
+
+impl HexDump {
+    /// Create a renderer.
+    pub fn new() -> HexDump {
+        HexDump {
+            offset: 0,
+            row: Vec::with_capacity(HEX_ROW),
+        }
+    }
+
+    fn push(&mut self, b: u8, output: &mut BytesMut) {
+        self.row.push(b);
+        if self.row.len() == HEX_ROW {
+            self.write_row(output);
+        }
+    }
+
+    fn write_row(&mut self, output: &mut BytesMut) {
+        let mut line = format!("{:08x} ", self.offset);
+        for i in 0..HEX_ROW {
+            if i % 8 == 0 {
+                line.push(' ');
+            }
+            match self.row.get(i) {
+                Some(b) => line.push_str(&format!("{:02x} ", b)),
+                None => line.push_str("   "),
+            }
+        }
+        line.push_str(" |");
+        for &b in &self.row {
+            line.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+        line.push_str("|\n");
+        output.put_slice(line.as_bytes());
+
+        self.offset += self.row.len() as u64;
+        self.row.clear();
+    }
+}
+
+impl Render for HexDump {
+    fn render(&mut self, input: &mut BytesMut, output: &mut BytesMut) {
+        for &b in input.split().iter() {
+            self.push(b, output);
+        }
+    }
+
+    fn pending(&self) -> bool {
+        !self.row.is_empty()
+    }
+
+    fn held(&self) -> usize {
+        self.row.len()
+    }
+
+    fn flush(&mut self, output: &mut BytesMut) {
+        if self.pending() {
+            self.write_row(output);
+        }
+    }
+
+    fn reset(&mut self, output: &mut BytesMut) {
+        self.flush(output);
+    }
+}
+
+/// Text with hexdump blocks of binary data.
+///
+/// Text is visualized as [Visualized](struct.Visualized.html) does. When `threshold`
+/// non-printable bytes continue, they are written as a hexdump block instead, until `threshold`
+/// printable characters continue or the input is idle.
+/// Offsets in blocks count all bytes received, including text.
+///
+/// ```
+///     # use serialcat::{encoding::Encoding, render::{Auto, Render as _}};
+///     # use bytes::BytesMut;
+///
+///     let mut input = BytesMut::from(&b"ok\n\x00\x01\x02\x03"[..]);
+///     let mut output = BytesMut::new();
+///     let mut renderer = Auto::new(4, Default::default(), Encoding::Utf8);
+///     renderer.render(&mut input, &mut output);
+///     renderer.flush(&mut output);
+///     assert_eq!(
+///         &output[..],
+///         &b"ok\n00000003  00 01 02 03                                       |....|\n"[..]
+///     );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Auto {
+    threshold: usize,
+    text: Visualized,
+    hex: HexDump,
+    /// Offset of the next byte to write.
+    offset: u64,
+    binary: bool,
+    /// Characters of the other kind and their bytes, held until `threshold` of them continue.
+    held: Vec<(GetChars, Vec<u8>)>,
+    line_start: bool,
+}
+
+impl Auto {
+    /// Create a renderer switching to hexdump after `threshold` non-printable bytes.
+    ///
+    /// Control characters passed through by the style are printable.
+    pub fn new(threshold: usize, style: Style, encoding: Encoding) -> Auto {
+        Auto {
+            threshold: threshold.max(1),
+            text: Visualized::new(style, encoding),
+            hex: HexDump::new(),
+            offset: 0,
+            binary: false,
+            held: Vec::new(),
+            line_start: true,
+        }
+    }
+
+    fn put(&mut self, ch: &GetChars, bytes: &[u8], output: &mut BytesMut) {
+        self.offset += bytes.len() as u64;
+        if self.binary {
+            for &b in bytes {
+                self.hex.push(b, output);
+            }
+        } else {
+            self.text.put(ch, output);
+            self.line_start = *ch == GetChars::Char('\n');
+        }
+    }
+
+    fn put_held(&mut self, output: &mut BytesMut) {
+        for (ch, bytes) in std::mem::take(&mut self.held) {
+            self.put(&ch, &bytes, output);
+        }
+    }
+
+    fn start_binary(&mut self, output: &mut BytesMut) {
+        self.text.reset(output);
+        if !self.line_start {
+            output.put_u8(b'\n');
+        }
+        self.hex = HexDump {
+            offset: self.offset,
+            row: Vec::with_capacity(HEX_ROW),
+        };
+        self.binary = true;
+    }
+
+    fn end_binary(&mut self, output: &mut BytesMut) {
+        self.hex.flush(output);
+        self.line_start = true;
+        self.binary = false;
+    }
+}
+
+impl Render for Auto {
+    fn render(&mut self, input: &mut BytesMut, output: &mut BytesMut) {
+        let mut chars = input.get_chars_in(self.text.encoding);
+        while let Some(ch) = chars.next() {
+            if self.text.style.passes(&ch) != self.binary {
+                // Continues the current kind
+                self.put_held(output);
+                self.put(&ch, chars.bytes(), output);
+                continue;
+            }
+
+            self.held.push((ch, chars.bytes().to_vec()));
+            if self.held.len() >= self.threshold {
+                if self.binary {
+                    self.end_binary(output);
+                } else {
+                    self.start_binary(output);
+                }
+                self.put_held(output);
+            }
+        }
+    }
+
+    fn pending(&self) -> bool {
+        !self.held.is_empty() || self.hex.pending() || self.text.pending()
+    }
+
+    fn held(&self) -> usize {
+        let held = self
+            .held
+            .iter()
+            .map(|(_, bytes)| bytes.len())
+            .sum::<usize>();
+        held + self.hex.held() + self.text.held()
+    }
+
+    fn flush(&mut self, output: &mut BytesMut) {
+        // The next burst starts as text
+        self.put_held(output);
+        if self.binary {
+            self.end_binary(output);
+        }
+        self.text.flush(output);
+    }
+
+    fn reset(&mut self, output: &mut BytesMut) {
+        self.flush(output);
+        self.text.reset(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render<R: Render>(renderer: &mut R, input: &[u8]) -> BytesMut {
+        let mut input = BytesMut::from(input);
+        let mut output = BytesMut::new();
+        renderer.render(&mut input, &mut output);
+        output
+    }
+
+    #[test]
+    fn parse_mode() {
+        for (s, mode) in &[
+            ("visualized", OutputMode::Visualized),
+            ("raw", OutputMode::Raw),
+            ("hex", OutputMode::Hex),
+            ("auto", OutputMode::Auto),
+        ] {
+            assert_eq!(s.parse::<OutputMode>().unwrap(), *mode);
+            assert_eq!(mode.to_string(), *s);
+        }
+        "text".parse::<OutputMode>().unwrap_err();
+    }
+
+    #[test]
+    fn raw() {
+        assert_eq!(&render(&mut Raw, b"a\x01\xff\xce")[..], b"a\x01\xff\xce");
+    }
+
+    #[test]
+    fn visualized() {
+        let mut renderer = Visualized::new(Style::default(), Encoding::Utf8);
+        assert_eq!(
+            &render(&mut renderer, b"a\tb\r\n\x1b\x7f\xc2\x85\xce")[..],
+            &b"a\tb\x1b[7m^M\x1b[m\n\x1b[7m^[^?M-^E"[..]
+        );
+
+        // Incomplete sequence is kept
+        let mut input = BytesMut::from(&b"\xce"[..]);
+        let mut output = BytesMut::new();
+        renderer.render(&mut input, &mut output);
+        assert_eq!(&output[..], b"");
+        input.put_slice(b"\x93");
+        renderer.render(&mut input, &mut output);
+        assert_eq!(&output[..], "\x1b[mΓ".as_bytes());
+
+        let mut output = BytesMut::new();
+        renderer.render(&mut BytesMut::from(&b"\x00"[..]), &mut output);
+        renderer.reset(&mut output);
+        renderer.reset(&mut output);
+        assert_eq!(&output[..], b"\x1b[7m^@\x1b[m");
+    }
+
+    #[test]
+    fn styles() {
+        let input = b"\r\n\t\x1b\x7f\xc2\x9b\xff";
+        for (ctrl, expected) in &[
+            (CtrlStyle::Caret, "^M\n^I^[^?M-^[<FF>"),
+            (CtrlStyle::Escape, "\\x0d\n\\x09\\x1b\\x7f\\u009b\\xff"),
+            (CtrlStyle::Picture, "␍\n␉␛␡<CSI><FF>"),
+            (CtrlStyle::Mnemonic, "<CR>\n<HT><ESC><DEL><CSI><0xFF>"),
+        ] {
+            let mut renderer = Visualized::new(
+                Style {
+                    ctrl: *ctrl,
+                    color: false,
+                    pass: "LF".parse().unwrap(),
+                    ansi: AnsiMode::Visualize,
+                },
+                Encoding::Utf8,
+            );
+            assert_eq!(&render(&mut renderer, input)[..], expected.as_bytes());
+        }
+
+        "pictures".parse::<CtrlStyle>().unwrap_err();
+        assert_eq!(
+            "ESC,cr,,nel".parse::<CtrlSet>().unwrap(),
+            CtrlSet(vec!['\x1b', '\r', '\u{85}'])
+        );
+        assert_eq!("".parse::<CtrlSet>().unwrap(), CtrlSet(vec![]));
+        "LF,SPACE".parse::<CtrlSet>().unwrap_err();
+    }
+
+    #[test]
+    fn ansi() {
+        let style = |ansi| Style {
+            ansi,
+            ..Style::default()
+        };
+        let input = b"\x1b[1;31mE\x1b[0m \x1b[2K\x1b(B\x1b[1\x01";
+
+        let mut renderer = Visualized::new(style(AnsiMode::Visualize), Encoding::Utf8);
+        assert_eq!(
+            &render(&mut renderer, input)[..],
+            &b"\x1b[7m^[\x1b[m[1;31mE\x1b[7m^[\x1b[m[0m \x1b[7m^[\x1b[m[2K\x1b[7m^[\x1b[m(B\x1b[7m^[\x1b[m[1\x1b[7m^A"[..]
+        );
+
+        // Malformed sequences are visualized
+        let mut renderer = Visualized::new(style(AnsiMode::Pass), Encoding::Utf8);
+        let mut output = render(&mut renderer, input);
+        assert_eq!(
+            &output[..],
+            &b"\x1b[1;31mE\x1b[0m \x1b[2K\x1b[7m^[\x1b[m(B\x1b[7m^[\x1b[m[1\x1b[7m^A"[..]
+        );
+        output.clear();
+        renderer.reset(&mut output);
+        assert_eq!(&output[..], b"\x1b[m");
+        output.clear();
+        renderer.reset(&mut output);
+        assert_eq!(&output[..], b"");
+
+        let mut renderer = Visualized::new(style(AnsiMode::Strip), Encoding::Utf8);
+        assert_eq!(
+            &render(&mut renderer, b"\x1b[1;31mE\x1b[0m\x1b[?25l.")[..],
+            b"E."
+        );
+
+        // Split sequence
+        let mut output = render(&mut renderer, b"a\x1b");
+        assert!(renderer.pending());
+        renderer.render(&mut BytesMut::from(&b"[3"[..]), &mut output);
+        renderer.render(&mut BytesMut::from(&b"2mb"[..]), &mut output);
+        assert!(!renderer.pending());
+        assert_eq!(&output[..], b"ab");
+
+        // Idle in a sequence
+        let mut output = render(&mut renderer, b"\x1b[");
+        renderer.flush(&mut output);
+        assert!(!renderer.pending());
+        assert_eq!(&output[..], b"\x1b[7m^[\x1b[m[");
+    }
+
+    #[test]
+    fn encoding() {
+        let mut renderer = Visualized::new(Style::default(), Encoding::ShiftJis);
+        let mut input = BytesMut::from(&b"\x82\xa0\x82"[..]);
+        let mut output = BytesMut::new();
+        renderer.render(&mut input, &mut output);
+        input.put_slice(b"\xa2\xa0");
+        renderer.render(&mut input, &mut output);
+        assert_eq!(&output[..], "あい\x1b[7m<A0>".as_bytes());
+
+        // Offsets count bytes in the encoding
+        let mut renderer = Auto::new(4, Style::default(), Encoding::EucJp);
+        let mut output = render(&mut renderer, b"\xb0\xa1\x00\x00\x00\x00");
+        renderer.flush(&mut output);
+        assert_eq!(
+            &output[..],
+            "亜\n00000002  00 00 00 00                                       |....|\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn hex() {
+        let mut renderer = HexDump::new();
+        assert_eq!(
+            &render(&mut renderer, b"Hello, World!\r\n\x00\xffabc")[..],
+            &b"00000000  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0d 0a 00  |Hello, World!...|\n"[..]
+        );
+        assert!(renderer.pending());
+
+        let mut output = BytesMut::new();
+        renderer.flush(&mut output);
+        assert!(!renderer.pending());
+        assert_eq!(
+            &output[..],
+            &b"00000010  ff 61 62 63                                       |.abc|\n"[..]
+        );
+
+        // The next row starts after the partial row
+        assert_eq!(
+            &render(&mut renderer, &[0x20; 16])[..],
+            &b"00000014  20 20 20 20 20 20 20 20  20 20 20 20 20 20 20 20  |                |\n"[..]
+        );
+        assert!(!renderer.pending());
+        let mut output = BytesMut::new();
+        renderer.flush(&mut output);
+        assert_eq!(&output[..], b"");
+    }
+
+    #[test]
+    fn auto() {
+        let mut renderer = Auto::new(4, Style::default(), Encoding::Utf8);
+
+        // Short runs of control characters are visualized in text
+        assert_eq!(
+            &render(&mut renderer, b"a\x01\x02b\n")[..],
+            &b"a\x1b[7m^A^B\x1b[mb\n"[..]
+        );
+        assert!(!renderer.pending());
+
+        // Binary block starts on a new line and ends when text continues
+        assert_eq!(
+            &render(&mut renderer, b"c\x00\xff\x80\x01\x02ab\x03")[..],
+            b"c\n"
+        );
+        assert!(renderer.pending());
+        let mut output = BytesMut::new();
+        renderer.render(&mut BytesMut::from(&b"defgh\n"[..]), &mut output);
+        assert_eq!(
+            &output[..],
+            &b"00000006  00 ff 80 01 02 61 62 03                           |.....ab.|\ndefgh\n"[..]
+        );
+
+        // Idle gap ends the block
+        assert_eq!(&render(&mut renderer, b"\x00\x00\x00\x00ab")[..], b"");
+        let mut output = BytesMut::new();
+        renderer.flush(&mut output);
+        renderer.render(&mut BytesMut::from(&b"c"[..]), &mut output);
+        assert_eq!(
+            &output[..],
+            &b"00000014  00 00 00 00 61 62                                 |....ab|\nc"[..]
+        );
+    }
+}
@@ -0,0 +1,489 @@
+//! Expect-style scripts
+//!
+//! A script has a statement per line. `#` starts a comment.
+//!
+//! * `send <string>`: send the string.
+//! * `expect <pattern> [-> <label>]... [timeout <duration> [-> <label>]]`: wait until one of the
+//!   patterns matches received bytes, and jump to the label of the pattern if given. Without
+//!   the label for timeout, the script fails when no pattern matches in time.
+//! * `sleep <duration>`: wait for the duration.
+//! * `set timeout <duration>`: change the default timeout of `expect`, initially 10 seconds.
+//! * `set <setting> <value>`: change a line setting or a modem line as the control socket does,
+//!   such as `set baud 115200` or `set dtr off`.
+//! * `reset [<pulse>]`: reset the board by DTR and RTS, such as `reset esp32-boot`, or as
+//!   `--reset` without the pulse.
+//! * `goto <label>`: jump to the label.
+//! * `exit [<status>]`: quit with the exit status, 0 by default.
+//! * `<label>:`: define a label.
+//!
+//! Strings are quoted by `"` or `'`. In `"`-quoted strings to send, `\r`, `\n`, `\t`, `\e`,
+//! `\0`, `\xHH`, `\\`, `\"` and `\'` are escapes. Patterns are regular expressions, which
+//! support the same escapes by themselves.
+//! Durations are such as `500ms`, `10s` or `1m`.
+
+use crate::{
+    control::{self, Command},
+    opt,
+};
+use anyhow::{bail, Context as _, Result};
+use regex::bytes::Regex;
+use std::{collections::HashMap, time::Duration};
+
+/// Default timeout of `expect`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Received bytes kept to find patterns.
+const MATCHER_LIMIT: usize = 64 * 1024;
+
+/// Parsed script.
+#[derive(Debug)]
+pub struct Script {
+    /// Statements in order, without comments, empty lines and labels.
+    pub lines: Vec<Line>,
+}
+
+/// Statement with its line number.
+#[derive(Debug)]
+pub struct Line {
+    /// Line number from 1.
+    pub number: usize,
+    /// Statement of the line.
+    pub statement: Statement,
+}
+
+/// Statement of scripts. Labels are resolved to indexes of [lines](struct.Script.html#structfield.lines).
+#[derive(Debug)]
+pub enum Statement {
+    /// Send the bytes.
+    Send(Vec<u8>),
+    /// Wait for one of the patterns.
+    Expect(Expect),
+    /// Wait for the duration.
+    Sleep(Duration),
+    /// Change the default timeout of `expect`.
+    SetTimeout(Duration),
+    /// Change a line setting or a modem line, or reset the board.
+    Set(Command),
+    /// Jump to the index of the line.
+    Goto(usize),
+    /// Quit with the exit status.
+    Exit(i32),
+}
+
+/// Patterns to wait for.
+#[derive(Debug)]
+pub struct Expect {
+    /// Patterns with the lines to jump to when they match, or `None` to continue.
+    pub patterns: Vec<(Regex, Option<usize>)>,
+    /// Timeout, or `None` for the default.
+    pub timeout: Option<Duration>,
+    /// Line to jump to on timeout, or `None` to fail.
+    pub on_timeout: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// Quoted string as is, without quotes. `true` if it is quoted by `"`.
+    Quoted(String, bool),
+}
+
+impl Script {
+    /// Parse a script.
+    ///
+    /// ```
+    ///     # use serialcat::script::{Script, Statement};
+    ///
+    ///     let script = Script::parse(
+    ///         "send '\\r'\n\
+    ///          expect 'login: ' -> login '# ' timeout 5s\n\
+    ///          exit\n\
+    ///          login:\n\
+    ///          send \"root\\r\"\n",
+    ///     )
+    ///     .unwrap();
+    ///     match &script.lines[1].statement {
+    ///         Statement::Expect(expect) => assert_eq!(expect.patterns[0].1, Some(3)),
+    ///         s => panic!("unexpected statement: {:?}", s),
+    ///     }
+    ///     assert!(Script::parse("goto nowhere").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<Script> {
+        let mut labels = HashMap::new();
+        let mut statements = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let tokens = tokenize(line).with_context(|| format!("Line {}", i + 1))?;
+            match tokens.as_slice() {
+                [] => {}
+                [Token::Word(word)] if word.ends_with(':') => {
+                    let label = &word[..word.len() - 1];
+                    if labels.insert(label.to_owned(), statements.len()).is_some() {
+                        bail!("Line {}: Duplicated label: {}", i + 1, label);
+                    }
+                }
+                _ => statements.push((i + 1, tokens)),
+            }
+        }
+
+        let lines = statements
+            .into_iter()
+            .map(|(number, tokens)| {
+                let statement = parse_statement(&tokens, &labels)
+                    .with_context(|| format!("Line {}", number))?;
+                Ok(Line { number, statement })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Script { lines })
+    }
+}
+
+fn parse_statement(tokens: &[Token], labels: &HashMap<String, usize>) -> Result<Statement> {
+    use Token::*;
+
+    let label = |label: &str| match labels.get(label) {
+        Some(&index) => Ok(index),
+        None => bail!("Unknown label: {}", label),
+    };
+    let word = |token: &Token| match token {
+        Word(word) => Ok(word.clone()),
+        Quoted(s, _) => bail!("Unexpected string: {}", s),
+    };
+
+    let name = word(&tokens[0])?;
+    let args = &tokens[1..];
+    let statement = match (name.as_str(), args) {
+        ("send", [Quoted(s, true)]) => Statement::Send(unescape(s)?),
+        ("send", [Quoted(s, false)]) => Statement::Send(s.as_bytes().to_vec()),
+        ("expect", _) => {
+            let mut expect = Expect {
+                patterns: Vec::new(),
+                timeout: None,
+                on_timeout: None,
+            };
+            let mut args = args.iter().peekable();
+            while let Some(Quoted(s, double)) = args.peek() {
+                args.next();
+                let pattern = if *double {
+                    s.replace("\\\"", "\"")
+                } else {
+                    s.clone()
+                };
+                let regex = Regex::new(&pattern)
+                    .with_context(|| format!("Invalid pattern: {}", pattern))?;
+                let target = match args.peek() {
+                    Some(Word(arrow)) if arrow == "->" => {
+                        args.next();
+                        Some(label(&word(args.next().context("Missing label")?)?)?)
+                    }
+                    _ => None,
+                };
+                expect.patterns.push((regex, target));
+            }
+            if expect.patterns.is_empty() {
+                bail!("Missing pattern");
+            }
+            match args.next() {
+                Some(Word(timeout)) if timeout == "timeout" => {
+                    let duration = word(args.next().context("Missing timeout")?)?;
+                    expect.timeout = Some(opt::duration_from_str(&duration)?);
+                    match args.next() {
+                        Some(Word(arrow)) if arrow == "->" => {
+                            let target = word(args.next().context("Missing label")?)?;
+                            expect.on_timeout = Some(label(&target)?);
+                        }
+                        Some(token) => bail!("Unexpected argument: {:?}", token),
+                        None => {}
+                    }
+                }
+                Some(token) => bail!("Unexpected argument: {:?}", token),
+                None => {}
+            }
+            if let Some(token) = args.next() {
+                bail!("Unexpected argument: {:?}", token);
+            }
+            Statement::Expect(expect)
+        }
+        ("sleep", [Word(duration)]) => Statement::Sleep(opt::duration_from_str(duration)?),
+        ("set", [Word(name), Word(duration)]) if name == "timeout" => {
+            Statement::SetTimeout(opt::duration_from_str(duration)?)
+        }
+        ("set", [Word(name), Word(value)]) => match name.as_str() {
+            "baud" | "data-bits" | "parity" | "stop-bits" | "flow-control" | "dtr" | "rts" => {
+                Statement::Set(control::parse(&format!("{} {}", name, value))?)
+            }
+            _ => bail!("Unknown setting: {}", name),
+        },
+        ("reset", []) => Statement::Set(Command::Reset(None)),
+        ("reset", [Word(pulse)]) => Statement::Set(control::parse(&format!("reset {}", pulse))?),
+        ("goto", [Word(target)]) => Statement::Goto(label(target)?),
+        ("exit", []) => Statement::Exit(0),
+        ("exit", [Word(status)]) => match status.parse() {
+            Ok(status) if (0..=255).contains(&status) => Statement::Exit(status),
+            _ => bail!("Invalid exit status: {}", status),
+        },
+        ("send", _) | ("sleep", _) | ("set", _) | ("reset", _) | ("goto", _) | ("exit", _) => {
+            bail!("Invalid arguments: {}", name)
+        }
+        _ => bail!("Unknown statement: {}", name),
+    };
+    Ok(statement)
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '#' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        // Keep escapes as is, to be interpreted later
+                        Some('\\') if c == '"' => {
+                            s.push('\\');
+                            s.extend(chars.next());
+                        }
+                        Some(c) => s.push(c),
+                        None => bail!("Unterminated string: {}{}", c, s),
+                    }
+                }
+                tokens.push(Token::Quoted(s, c == '"'));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '"' || c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('r') => b'\r',
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('e') => 0x1b,
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => byte,
+                    _ => bail!("Invalid escape: \\x{}", hex),
+                }
+            }
+            Some(c) => bail!("Unknown escape: \\{}", c),
+            None => bail!("Unterminated escape"),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Buffer of received bytes to find patterns.
+///
+/// Bytes up to the end of a match are consumed, so that the next `expect` sees only bytes after
+/// that.
+///
+/// ```
+///     # use serialcat::script::Matcher;
+///     # use regex::bytes::Regex;
+///
+///     let patterns = [Regex::new("login: ").unwrap(), Regex::new(r"\$ ").unwrap()];
+///     let mut matcher = Matcher::default();
+///     matcher.push(b"Welcome\r\nlog");
+///     assert_eq!(matcher.find(&patterns), None);
+///     matcher.push(b"in: ");
+///     assert_eq!(matcher.find(&patterns), Some(0));
+///     assert_eq!(matcher.find(&patterns), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct Matcher {
+    buffer: Vec<u8>,
+}
+
+impl Matcher {
+    /// Add received bytes. Only the last 64 KiB are kept.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() > MATCHER_LIMIT {
+            let excess = self.buffer.len() - MATCHER_LIMIT;
+            self.buffer.drain(..excess);
+        }
+    }
+
+    /// Find the pattern whose match ends first, and consume bytes through the match.
+    ///
+    /// Returns the index of the pattern. If matches end at the same byte, the former pattern
+    /// wins.
+    pub fn find<'a, I>(&mut self, patterns: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = &'a Regex>,
+    {
+        let (index, end) = patterns
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, pattern)| pattern.find(&self.buffer).map(|m| (i, m.end())))
+            .min_by_key(|&(_, end)| end)?;
+        self.buffer.drain(..end);
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(script: &str) -> String {
+        format!("{:#}", Script::parse(script).unwrap_err())
+    }
+
+    #[test]
+    fn parse() {
+        let script = Script::parse(
+            "# Log in\n\
+             \n\
+             set timeout 3s\n\
+             set baud 115200\n\
+             retry:\n\
+             \x20 send \"\\r\"  # Wake up\n\
+             \x20 expect \"login: \" -> login '(?m)^# $' -> shell timeout 500ms -> retry\n\
+             login:\n\
+             \x20 send 'root\\r'\n\
+             \x20 expect \"\\\"#\\\" \"\n\
+             shell:\n\
+             \x20 sleep 1s\n\
+             \x20 goto done\n\
+             done:\n\
+             set dtr off\n\
+             reset esp32\n\
+             exit 3\n",
+        )
+        .unwrap();
+
+        let lines = script
+            .lines
+            .iter()
+            .map(|line| (line.number, format!("{:?}", line.statement)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                (3, "SetTimeout(3s)".to_owned()),
+                (4, "Set(SetBaudRate(115200))".to_owned()),
+                (6, "Send([13])".to_owned()),
+                (
+                    7,
+                    "Expect(Expect { patterns: [(login: , Some(4)), ((?m)^# $, Some(6))], \
+                     timeout: Some(500ms), on_timeout: Some(2) })"
+                        .to_owned()
+                ),
+                (9, "Send([114, 111, 111, 116, 92, 114])".to_owned()),
+                (
+                    10,
+                    "Expect(Expect { patterns: [(\"#\" , None)], timeout: None, on_timeout: None })"
+                        .to_owned()
+                ),
+                (12, "Sleep(1s)".to_owned()),
+                (13, "Goto(8)".to_owned()),
+                (15, "Set(SetDtr(false))".to_owned()),
+                (16, "Set(Reset(Some(Esp32)))".to_owned()),
+                (17, "Exit(3)".to_owned()),
+            ]
+        );
+
+        // A label at the end jumps to the end
+        let script = Script::parse("goto end\nsend 'a'\nend:").unwrap();
+        assert_eq!(format!("{:?}", script.lines[0].statement), "Goto(2)");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error("foo"), "Line 1: Unknown statement: foo");
+        assert_eq!(error("\nsend"), "Line 2: Invalid arguments: send");
+        assert_eq!(error("send \"a"), "Line 1: Unterminated string: \"a");
+        assert_eq!(error("send \"\\q\""), "Line 1: Unknown escape: \\q");
+        assert_eq!(error("send \"\\x4\""), "Line 1: Invalid escape: \\x4");
+        assert_eq!(error("expect"), "Line 1: Missing pattern");
+        assert_eq!(error("expect 'a' -> b"), "Line 1: Unknown label: b");
+        assert_eq!(error("expect 'a' timeout"), "Line 1: Missing timeout");
+        assert_eq!(
+            error("expect 'a' timeout 1x"),
+            "Line 1: Unknown duration unit: 1x"
+        );
+        assert!(error("expect '('").starts_with("Line 1: Invalid pattern: ("));
+        assert_eq!(error("set dsr on"), "Line 1: Unknown setting: dsr");
+        assert_eq!(
+            error("set dtr keep"),
+            "Line 1: Line level keep is only for opening the port"
+        );
+        assert_eq!(
+            error("set rts low"),
+            "Line 1: Unknown line level: low (on, off or keep)"
+        );
+        assert_eq!(error("reset en"), "Line 1: Unknown reset pulse: en");
+        assert_eq!(error("set baud 0"), "Line 1: Invalid baud rate: 0");
+        assert_eq!(error("exit -"), "Line 1: Invalid exit status: -");
+        assert_eq!(error("exit 256"), "Line 1: Invalid exit status: 256");
+        assert_eq!(error("exit -1"), "Line 1: Invalid exit status: -1");
+        assert_eq!(error("a:\na:"), "Line 2: Duplicated label: a");
+    }
+
+    #[test]
+    fn unescape_strings() {
+        assert_eq!(
+            unescape(r#"a\r\n\t\e\0\\\"\'\x7f\xFFé"#).unwrap(),
+            b"a\r\n\t\x1b\0\\\"'\x7f\xff\xc3\xa9"
+        );
+    }
+
+    #[test]
+    fn matcher() {
+        let patterns = [
+            Regex::new("OK").unwrap(),
+            Regex::new("ERROR").unwrap(),
+            Regex::new("O").unwrap(),
+        ];
+        let mut matcher = Matcher::default();
+
+        // The match ending first wins
+        matcher.push(b"ERROR OK");
+        assert_eq!(matcher.find(&patterns[..2]), Some(1));
+        assert_eq!(matcher.find(&patterns[..2]), Some(0));
+        assert_eq!(matcher.find(&patterns[..2]), None);
+
+        // The former wins on the same end
+        matcher.push(b"OK");
+        assert_eq!(matcher.find(&patterns), Some(2));
+        assert_eq!(matcher.find(&patterns), None);
+
+        // Old bytes are dropped
+        matcher.push(b"OK");
+        matcher.push(&vec![b'.'; MATCHER_LIMIT]);
+        assert_eq!(matcher.find(&patterns), None);
+    }
+}
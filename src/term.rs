@@ -0,0 +1,127 @@
+//! Terminal control
+
+use std::{io, mem::MaybeUninit, os::unix::io::RawFd};
+
+/// Check whether the file descriptor refers to a terminal.
+pub fn is_terminal(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+fn get_termios(fd: RawFd) -> io::Result<libc::termios> {
+    let mut termios = MaybeUninit::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { termios.assume_init() })
+}
+
+fn set_termios(fd: RawFd, termios: &libc::termios) -> io::Result<()> {
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Guard of terminal raw mode.
+///
+/// While this guard lives, the terminal does not buffer lines, echo input or generate signals
+/// from keys, so every keystroke is readable immediately.
+/// Output processing is kept as is, so `\n` still moves to the beginning of the next line.
+/// The original settings are restored when this guard is dropped.
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Switch the terminal to raw mode.
+    ///
+    /// Returns `Ok(None)` if `fd` is not a terminal.
+    pub fn enable(fd: RawFd) -> io::Result<Option<RawMode>> {
+        if !is_terminal(fd) {
+            return Ok(None);
+        }
+
+        let original = get_termios(fd)?;
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_oflag = original.c_oflag;
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        set_termios(fd, &raw)?;
+
+        Ok(Some(RawMode { fd, original }))
+    }
+
+    /// End-of-file character of the original settings (usually `Ctrl-D`).
+    pub fn eof_char(&self) -> u8 {
+        self.original.c_cc[libc::VEOF]
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = set_termios(self.fd, &self.original);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    fn open_pty() -> (RawFd, RawFd) {
+        let mut master = 0;
+        let mut slave = 0;
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        assert_eq!(ret, 0);
+        (master, slave)
+    }
+
+    #[test]
+    fn raw_mode() {
+        let (master, slave) = open_pty();
+        let original = get_termios(slave).unwrap();
+        assert_ne!(original.c_lflag & libc::ICANON, 0);
+
+        let raw = RawMode::enable(slave).unwrap().unwrap();
+        assert_eq!(raw.eof_char(), original.c_cc[libc::VEOF]);
+        let termios = get_termios(slave).unwrap();
+        assert_eq!(
+            termios.c_lflag & (libc::ICANON | libc::ECHO | libc::ISIG),
+            0
+        );
+        assert_eq!(termios.c_oflag, original.c_oflag);
+
+        drop(raw);
+        let termios = get_termios(slave).unwrap();
+        assert_eq!(termios.c_lflag, original.c_lflag);
+        assert_eq!(termios.c_iflag, original.c_iflag);
+
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn raw_mode_not_terminal() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        assert!(!is_terminal(fds[0]));
+        assert!(RawMode::enable(fds[0]).unwrap().is_none());
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
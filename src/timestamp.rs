@@ -0,0 +1,301 @@
+//! Timestamps of received lines
+
+use anyhow::{bail, Error, Result};
+use bytes::BufMut;
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use std::{collections::VecDeque, str::FromStr, time::Instant};
+
+/// Format of timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampMode {
+    /// Wall clock time in ISO 8601, such as `2020-01-02T03:04:05.678+09:00`.
+    Wall,
+    /// Seconds since start.
+    Monotonic,
+    /// Seconds since the previous line.
+    Delta,
+}
+
+impl FromStr for TimestampMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TimestampMode> {
+        match s {
+            "wall" => Ok(TimestampMode::Wall),
+            "monotonic" => Ok(TimestampMode::Monotonic),
+            "delta" => Ok(TimestampMode::Delta),
+            _ => bail!("Unknown timestamp mode: {}", s),
+        }
+    }
+}
+
+/// Time when bytes were read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadTime {
+    /// Monotonic time.
+    pub instant: Instant,
+    /// Wall clock time.
+    pub wall: DateTime<FixedOffset>,
+}
+
+impl ReadTime {
+    /// Current time in the local timezone.
+    pub fn now() -> ReadTime {
+        ReadTime {
+            instant: Instant::now(),
+            wall: chrono::Local::now().into(),
+        }
+    }
+}
+
+/// Times of bytes held by a renderer, such as a partial hexdump row, so that the lines rendered
+/// later are stamped with the time when their first byte was read.
+#[derive(Debug, Clone, Default)]
+pub struct HeldTimes {
+    /// Times and lengths of chunks not rendered yet, from the oldest.
+    chunks: VecDeque<(ReadTime, usize)>,
+}
+
+impl HeldTimes {
+    /// Add a chunk of bytes to render.
+    pub fn push(&mut self, time: ReadTime, len: usize) {
+        self.chunks.push_back((time, len));
+    }
+
+    /// Time of the oldest byte not rendered yet.
+    pub fn first(&self) -> Option<ReadTime> {
+        self.chunks.front().map(|&(time, _)| time)
+    }
+
+    /// Time of the latest chunk not rendered yet.
+    pub fn last(&self) -> Option<ReadTime> {
+        self.chunks.back().map(|&(time, _)| time)
+    }
+
+    /// Forget the bytes rendered, when the last `held` bytes are still held.
+    pub fn release(&mut self, held: usize) {
+        let total = self.chunks.iter().map(|&(_, len)| len).sum::<usize>();
+        let mut rendered = total.saturating_sub(held);
+        while let Some((_, len)) = self.chunks.front_mut() {
+            if *len > rendered {
+                *len -= rendered;
+                break;
+            }
+            rendered -= *len;
+            self.chunks.pop_front();
+        }
+    }
+
+    /// Forget all bytes, such as when they are discarded.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+}
+
+/// Streaming inserter of timestamps at the beginning of every line.
+///
+/// The timestamp of a line is the time when the first byte of the line was read.
+/// A timestamp is not inserted after the last LF until the next byte comes.
+/// Rendered lines are stamped by [stamp_held](#method.stamp_held) with [HeldTimes](struct.HeldTimes.html).
+///
+/// ```
+///     # use serialcat::timestamp::{ReadTime, Timestamper, TimestampMode};
+///     # use bytes::BytesMut;
+///     # use std::time::Duration;
+///
+///     let start = ReadTime::now();
+///     let mut timestamper = Timestamper::new(TimestampMode::Monotonic, start.instant);
+///     let mut output = BytesMut::new();
+///     let time = ReadTime {
+///         instant: start.instant + Duration::from_millis(1500),
+///         ..start
+///     };
+///     timestamper.stamp(b"abc\n", time, &mut output);
+///     assert_eq!(&output[..], b"[    1.500000] abc\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Timestamper {
+    mode: TimestampMode,
+    start: Instant,
+    /// Time of the previous line.
+    previous: Instant,
+    line_start: bool,
+}
+
+impl Timestamper {
+    /// Create a timestamper, counting from `start`.
+    pub fn new(mode: TimestampMode, start: Instant) -> Timestamper {
+        Timestamper {
+            mode,
+            start,
+            previous: start,
+            line_start: true,
+        }
+    }
+
+    /// Copy the input read at `time` to the output, inserting timestamps.
+    pub fn stamp<B>(&mut self, input: &[u8], time: ReadTime, output: &mut B)
+    where
+        B: BufMut,
+    {
+        self.stamp_held(input, time, time, output);
+    }
+
+    /// Copy the input to the output as [stamp](#method.stamp) does, but stamp the first line
+    /// starting in the input with `first`, when bytes held before the input at `time` were read.
+    pub fn stamp_held<B>(&mut self, input: &[u8], first: ReadTime, time: ReadTime, output: &mut B)
+    where
+        B: BufMut,
+    {
+        let mut line_time = first;
+        for line in input.split_inclusive(|&b| b == b'\n') {
+            if self.line_start {
+                let time = std::mem::replace(&mut line_time, time);
+                let timestamp = match self.mode {
+                    TimestampMode::Wall => {
+                        format!(
+                            "[{}] ",
+                            time.wall.to_rfc3339_opts(SecondsFormat::Millis, true)
+                        )
+                    }
+                    TimestampMode::Monotonic => {
+                        format!("[{:>12.6}] ", (time.instant - self.start).as_secs_f64())
+                    }
+                    TimestampMode::Delta => {
+                        format!("[+{:.6}] ", (time.instant - self.previous).as_secs_f64())
+                    }
+                };
+                output.put_slice(timestamp.as_bytes());
+                self.previous = time.instant;
+            }
+            output.put_slice(line);
+            self.line_start = line.ends_with(b"\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BytesMut;
+    use chrono::TimeZone as _;
+    use std::time::Duration;
+
+    fn time(start: Instant, millis: u64) -> ReadTime {
+        let wall = FixedOffset::east_opt(9 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 2, 3, 4, 5)
+            .unwrap();
+        ReadTime {
+            instant: start + Duration::from_millis(millis),
+            wall: wall + chrono::Duration::milliseconds(millis as i64),
+        }
+    }
+
+    #[test]
+    fn parse_mode() {
+        assert_eq!(
+            "wall".parse::<TimestampMode>().unwrap(),
+            TimestampMode::Wall
+        );
+        assert_eq!(
+            "monotonic".parse::<TimestampMode>().unwrap(),
+            TimestampMode::Monotonic
+        );
+        assert_eq!(
+            "delta".parse::<TimestampMode>().unwrap(),
+            TimestampMode::Delta
+        );
+        "utc".parse::<TimestampMode>().unwrap_err();
+    }
+
+    #[test]
+    fn wall() {
+        let start = Instant::now();
+        let mut timestamper = Timestamper::new(TimestampMode::Wall, start);
+        let mut output = BytesMut::new();
+        timestamper.stamp(b"a\nb", time(start, 1), &mut output);
+        assert_eq!(
+            &output[..],
+            &b"[2020-01-02T03:04:05.001+09:00] a\n[2020-01-02T03:04:05.001+09:00] b"[..]
+        );
+    }
+
+    #[test]
+    fn monotonic() {
+        let start = Instant::now();
+        let mut timestamper = Timestamper::new(TimestampMode::Monotonic, start);
+        let mut output = BytesMut::new();
+        timestamper.stamp(b"a\n", time(start, 1000), &mut output);
+        // The next line starts at 2000ms, not at 3000ms
+        timestamper.stamp(b"b", time(start, 2000), &mut output);
+        timestamper.stamp(b"c\n", time(start, 3000), &mut output);
+        timestamper.stamp(b"", time(start, 4000), &mut output);
+        timestamper.stamp(b"\n", time(start, 5000), &mut output);
+        assert_eq!(
+            &output[..],
+            &b"[    1.000000] a\n[    2.000000] bc\n[    5.000000] \n"[..]
+        );
+    }
+
+    #[test]
+    fn held_times() {
+        let start = Instant::now();
+        let mut held = HeldTimes::default();
+        assert_eq!(held.first(), None);
+        held.push(time(start, 1), 3);
+        held.push(time(start, 2), 0);
+        held.push(time(start, 3), 4);
+        held.release(5);
+        assert_eq!(held.first(), Some(time(start, 1)));
+        held.release(4);
+        assert_eq!(held.first(), Some(time(start, 3)));
+        assert_eq!(held.last(), Some(time(start, 3)));
+        held.release(0);
+        assert_eq!(held.first(), None);
+    }
+
+    #[test]
+    fn idle_hex_row() {
+        use crate::render::{HexDump, Render as _};
+
+        // A partial row flushed when idle keeps the time of its first byte
+        let start = Instant::now();
+        let mut renderer = HexDump::new();
+        let mut held = HeldTimes::default();
+        let mut timestamper = Timestamper::new(TimestampMode::Monotonic, start);
+        let mut rendered = BytesMut::new();
+        let mut output = BytesMut::new();
+        for (millis, bytes) in &[(1000, &b"0123456789abcdefgh"[..]), (1500, b"ij")] {
+            held.push(time(start, *millis), bytes.len());
+            let first = held.first().unwrap();
+            renderer.render(&mut BytesMut::from(*bytes), &mut rendered);
+            timestamper.stamp_held(&rendered, first, held.last().unwrap(), &mut output);
+            rendered.clear();
+            held.release(renderer.held());
+        }
+        let first = held.first().unwrap();
+        renderer.flush(&mut rendered);
+        timestamper.stamp_held(&rendered, first, time(start, 9000), &mut output);
+        assert_eq!(
+            String::from_utf8(output.to_vec()).unwrap(),
+            "[    1.000000] 00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+             [    1.000000] 00000010  67 68 69 6a                                       |ghij|\n"
+        );
+    }
+
+    #[test]
+    fn delta() {
+        let start = Instant::now();
+        let mut timestamper = Timestamper::new(TimestampMode::Delta, start);
+        let mut output = BytesMut::new();
+        timestamper.stamp(b"a\nb\n", time(start, 1000), &mut output);
+        timestamper.stamp(b"c", time(start, 1250), &mut output);
+        timestamper.stamp(b"\nd", time(start, 3000), &mut output);
+        assert_eq!(
+            &output[..],
+            &b"[+1.000000] a\n[+0.000000] b\n[+0.250000] c\n[+1.750000] d"[..]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Line ending translation
+
+use anyhow::{bail, Error, Result};
+use bytes::BufMut;
+use std::str::FromStr;
+
+/// Translation of outgoing bytes.
+///
+/// This is parsed from comma separated list of the following mappings:
+///
+/// | Mapping  | Description          |
+/// |----------|----------------------|
+/// | `crlf`   | Map CR to LF         |
+/// | `crcrlf` | Map CR to CRLF       |
+/// | `igncr`  | Drop CR              |
+/// | `lfcr`   | Map LF to CR         |
+/// | `lfcrlf` | Map LF to CRLF       |
+/// | `ignlf`  | Drop LF              |
+/// | `delbs`  | Map DEL to BS        |
+/// | `bsdel`  | Map BS to DEL        |
+///
+/// A CRLF pair is translated as a single line break,
+/// see [OutputTranslator](struct.OutputTranslator.html).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputMap {
+    /// Replacement of CR, or `None` to send it as is.
+    pub cr: Option<&'static [u8]>,
+    /// Replacement of LF, or `None` to send it as is.
+    pub lf: Option<&'static [u8]>,
+    /// Map DEL to BS.
+    pub del_bs: bool,
+    /// Map BS to DEL.
+    pub bs_del: bool,
+}
+
+impl FromStr for OutputMap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputMap> {
+        let mut map = OutputMap::default();
+        for name in s.split(',').filter(|name| !name.is_empty()) {
+            let (target, replacement): (_, &'static [u8]) = match name {
+                "crlf" => (&mut map.cr, b"\n"),
+                "crcrlf" => (&mut map.cr, b"\r\n"),
+                "igncr" => (&mut map.cr, b""),
+                "lfcr" => (&mut map.lf, b"\r"),
+                "lfcrlf" => (&mut map.lf, b"\r\n"),
+                "ignlf" => (&mut map.lf, b""),
+                "delbs" => {
+                    map.del_bs = true;
+                    continue;
+                }
+                "bsdel" => {
+                    map.bs_del = true;
+                    continue;
+                }
+                _ => bail!("Unknown mapping: {}", name),
+            };
+            if target.is_some() {
+                bail!("Conflicting mapping: {}", name);
+            }
+            *target = Some(replacement);
+        }
+        Ok(map)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cr {
+    /// The last byte was not CR.
+    None,
+    /// The last byte was CR, and it was translated to the bytes ending with this byte.
+    Sent(u8),
+    /// The last byte was CR, and it was dropped.
+    Dropped,
+}
+
+/// Streaming translator of outgoing bytes.
+///
+/// An LF following a CR forms a CRLF pair, even if they are given in different calls of
+/// [translate](#method.translate).
+/// The pair is translated to a single line break: the LF is dropped if the CR was already translated
+/// to a line ending with LF, and a CR is not repeated if the CR was already sent.
+///
+/// ```
+///     # use serialcat::translate::OutputTranslator;
+///     # use bytes::BytesMut;
+///
+///     let mut translator = OutputTranslator::new("lfcrlf".parse().unwrap());
+///     let mut output = BytesMut::new();
+///     translator.translate(b"a\nb\r", &mut output);
+///     translator.translate(b"\nc", &mut output);
+///     assert_eq!(&output[..], b"a\r\nb\r\nc");
+/// ```
+#[derive(Debug, Clone)]
+pub struct OutputTranslator {
+    map: OutputMap,
+    cr: Cr,
+}
+
+impl OutputTranslator {
+    /// Create a translator.
+    pub fn new(map: OutputMap) -> OutputTranslator {
+        OutputTranslator { map, cr: Cr::None }
+    }
+
+    /// Translate the input and append it to the output.
+    pub fn translate<B>(&mut self, input: &[u8], output: &mut B)
+    where
+        B: BufMut,
+    {
+        for &b in input {
+            let cr = std::mem::replace(&mut self.cr, Cr::None);
+            match b {
+                b'\r' => {
+                    let replacement = self.map.cr.unwrap_or(b"\r");
+                    output.put_slice(replacement);
+                    self.cr = match replacement.last() {
+                        Some(&last) => Cr::Sent(last),
+                        None => Cr::Dropped,
+                    };
+                }
+                b'\n' => {
+                    let replacement = self.map.lf.unwrap_or(b"\n");
+                    match cr {
+                        Cr::Sent(b'\n') => {}
+                        Cr::Sent(b'\r') => {
+                            let replacement =
+                                replacement.strip_prefix(b"\r").unwrap_or(replacement);
+                            output.put_slice(replacement);
+                        }
+                        _ => output.put_slice(replacement),
+                    }
+                }
+                0x7f if self.map.del_bs => output.put_u8(0x08),
+                0x08 if self.map.bs_del => output.put_u8(0x7f),
+                _ => output.put_u8(b),
+            }
+        }
+    }
+}
+
+/// Translation of incoming line endings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMap {
+    /// Display as is.
+    None,
+    /// Map CRLF to LF.
+    CrLf,
+    /// Map CR and CRLF to LF.
+    Cr,
+    /// Drop CR.
+    IgnoreCr,
+    /// Map LFCR to LF.
+    LfCr,
+}
+
+impl FromStr for InputMap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<InputMap> {
+        match s {
+            "none" => Ok(InputMap::None),
+            "crlf" => Ok(InputMap::CrLf),
+            "cr" => Ok(InputMap::Cr),
+            "ignore-cr" => Ok(InputMap::IgnoreCr),
+            "lfcr" => Ok(InputMap::LfCr),
+            _ => bail!("Unknown mapping: {}", s),
+        }
+    }
+}
+
+/// Streaming translator of incoming line endings.
+///
+/// A pair of line ending characters is recognized even if they are given in different calls of
+/// [translate](#method.translate).
+/// In [CrLf](enum.InputMap.html#variant.CrLf) mode, a CR at the end of the input is kept until the
+/// next input shows whether it is a part of CRLF, or until [flush](#method.flush).
+///
+/// ```
+///     # use serialcat::translate::{InputMap, InputTranslator};
+///     # use bytes::BytesMut;
+///
+///     let mut translator = InputTranslator::new(InputMap::CrLf);
+///     let mut output = BytesMut::new();
+///     translator.translate(b"a\r", &mut output);
+///     assert_eq!(&output[..], b"a");
+///     translator.translate(b"\nb\rc", &mut output);
+///     assert_eq!(&output[..], b"a\nb\rc");
+/// ```
+#[derive(Debug, Clone)]
+pub struct InputTranslator {
+    map: InputMap,
+    /// The last byte of the previous input, if it may form a pair with the next byte.
+    last: Option<u8>,
+}
+
+impl InputTranslator {
+    /// Create a translator.
+    pub fn new(map: InputMap) -> InputTranslator {
+        InputTranslator { map, last: None }
+    }
+
+    /// Translate the input and append it to the output.
+    pub fn translate<B>(&mut self, input: &[u8], output: &mut B)
+    where
+        B: BufMut,
+    {
+        for &b in input {
+            let last = self.last.take();
+            match (self.map, last, b) {
+                (InputMap::None, _, _) => output.put_u8(b),
+                (InputMap::CrLf, Some(b'\r'), b'\n') => output.put_u8(b'\n'),
+                (InputMap::CrLf, Some(b'\r'), b'\r') => {
+                    output.put_u8(b'\r');
+                    self.last = Some(b'\r');
+                }
+                (InputMap::CrLf, Some(b'\r'), _) => {
+                    output.put_u8(b'\r');
+                    output.put_u8(b);
+                }
+                (InputMap::CrLf, _, b'\r') => self.last = Some(b'\r'),
+                (InputMap::Cr, _, b'\r') => {
+                    output.put_u8(b'\n');
+                    self.last = Some(b'\r');
+                }
+                (InputMap::Cr, Some(b'\r'), b'\n') => {}
+                (InputMap::IgnoreCr, _, b'\r') => {}
+                (InputMap::LfCr, _, b'\n') => {
+                    output.put_u8(b'\n');
+                    self.last = Some(b'\n');
+                }
+                (InputMap::LfCr, Some(b'\n'), b'\r') => {}
+                _ => output.put_u8(b),
+            }
+        }
+    }
+
+    /// Whether a CR is kept for the next input.
+    pub fn pending(&self) -> bool {
+        self.map == InputMap::CrLf && self.last == Some(b'\r')
+    }
+
+    /// Write the CR kept, such as when no more bytes come for a while.
+    pub fn flush<B>(&mut self, output: &mut B)
+    where
+        B: BufMut,
+    {
+        if self.pending() {
+            output.put_u8(b'\r');
+        }
+        self.last = None;
+    }
+
+    /// Forget the last byte, such as when it was received with the old line settings.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BytesMut;
+
+    fn translate(map: &str, inputs: &[&[u8]]) -> Vec<u8> {
+        let mut translator = OutputTranslator::new(map.parse().unwrap());
+        let mut output = BytesMut::new();
+        for input in inputs {
+            translator.translate(input, &mut output);
+        }
+        output.to_vec()
+    }
+
+    #[test]
+    fn parse_output_map() {
+        assert_eq!("".parse::<OutputMap>().unwrap(), OutputMap::default());
+        assert_eq!(
+            "crlf,ignlf,delbs".parse::<OutputMap>().unwrap(),
+            OutputMap {
+                cr: Some(b"\n"),
+                lf: Some(b""),
+                del_bs: true,
+                bs_del: false,
+            }
+        );
+        "lfcr,lfcrlf".parse::<OutputMap>().unwrap_err();
+        "crlf,igncr".parse::<OutputMap>().unwrap_err();
+        "lf".parse::<OutputMap>().unwrap_err();
+    }
+
+    #[test]
+    fn translate_output() {
+        assert_eq!(
+            translate("", &[b"a\rb\nc\r\n\x7f\x08"]),
+            b"a\rb\nc\r\n\x7f\x08"
+        );
+        assert_eq!(translate("lfcr", &[b"a\nb\n\n"]), b"a\rb\r\r");
+        assert_eq!(translate("lfcrlf", &[b"a\nb\n\n"]), b"a\r\nb\r\n\r\n");
+        assert_eq!(translate("ignlf", &[b"a\nb\n"]), b"ab");
+        assert_eq!(translate("crlf", &[b"a\rb\r"]), b"a\nb\n");
+        assert_eq!(translate("crcrlf", &[b"a\rb\r"]), b"a\r\nb\r\n");
+        assert_eq!(translate("igncr", &[b"a\rb\r"]), b"ab");
+        assert_eq!(translate("delbs", &[b"a\x7f\x08"]), b"a\x08\x08");
+        assert_eq!(translate("bsdel", &[b"a\x7f\x08"]), b"a\x7f\x7f");
+        assert_eq!(translate("delbs,bsdel", &[b"a\x7f\x08"]), b"a\x08\x7f");
+    }
+
+    #[test]
+    fn translate_output_crlf_pair() {
+        for inputs in &[
+            &[&b"a\r\nb"[..]][..],
+            &[b"a\r", b"\nb"],
+            &[b"a", b"\r", b"\n", b"b"],
+        ] {
+            assert_eq!(translate("", inputs), b"a\r\nb");
+            assert_eq!(translate("lfcr", inputs), b"a\rb");
+            assert_eq!(translate("lfcrlf", inputs), b"a\r\nb");
+            assert_eq!(translate("ignlf", inputs), b"a\rb");
+            assert_eq!(translate("crlf", inputs), b"a\nb");
+            assert_eq!(translate("crcrlf", inputs), b"a\r\nb");
+            assert_eq!(translate("igncr", inputs), b"a\nb");
+            assert_eq!(translate("igncr,lfcrlf", inputs), b"a\r\nb");
+            assert_eq!(translate("crcrlf,lfcrlf", inputs), b"a\r\nb");
+        }
+
+        // Not a pair
+        assert_eq!(translate("lfcrlf", &[b"a\r", b"b\n"]), b"a\rb\r\n");
+        assert_eq!(translate("crlf", &[b"\n\r"]), b"\n\n");
+    }
+
+    fn translate_input(map: InputMap, inputs: &[&[u8]]) -> Vec<u8> {
+        let mut translator = InputTranslator::new(map);
+        let mut output = BytesMut::new();
+        for input in inputs {
+            translator.translate(input, &mut output);
+        }
+        output.to_vec()
+    }
+
+    #[test]
+    fn parse_input_map() {
+        for (s, map) in &[
+            ("none", InputMap::None),
+            ("crlf", InputMap::CrLf),
+            ("cr", InputMap::Cr),
+            ("ignore-cr", InputMap::IgnoreCr),
+            ("lfcr", InputMap::LfCr),
+        ] {
+            assert_eq!(s.parse::<InputMap>().unwrap(), *map);
+        }
+        "lf".parse::<InputMap>().unwrap_err();
+    }
+
+    #[test]
+    fn translate_input_map() {
+        let input = &[&b"a\r\nb\rc\nd\n\re\r\r\n"[..]];
+        assert_eq!(translate_input(InputMap::None, input), input[0]);
+        assert_eq!(
+            translate_input(InputMap::CrLf, input),
+            b"a\nb\rc\nd\n\re\r\n"
+        );
+        assert_eq!(translate_input(InputMap::Cr, input), b"a\nb\nc\nd\n\ne\n\n");
+        assert_eq!(translate_input(InputMap::IgnoreCr, input), b"a\nbc\nd\ne\n");
+        assert_eq!(
+            translate_input(InputMap::LfCr, input),
+            b"a\r\nb\rc\nd\ne\r\r\n"
+        );
+    }
+
+    #[test]
+    fn translate_input_split() {
+        for inputs in &[
+            &[&b"a\r\nb\n\rc"[..]][..],
+            &[b"a\r", b"\nb\n", b"\rc"],
+            &[b"a", b"\r", b"\n", b"b", b"\n", b"\r", b"c"],
+        ] {
+            assert_eq!(translate_input(InputMap::CrLf, inputs), b"a\nb\n\rc");
+            assert_eq!(translate_input(InputMap::Cr, inputs), b"a\nb\n\nc");
+            assert_eq!(translate_input(InputMap::IgnoreCr, inputs), b"a\nb\nc");
+            assert_eq!(translate_input(InputMap::LfCr, inputs), b"a\r\nb\nc");
+        }
+
+        // CR is kept until the next input
+        let mut translator = InputTranslator::new(InputMap::CrLf);
+        let mut output = BytesMut::new();
+        translator.translate(b"a\r", &mut output);
+        assert_eq!(&output[..], b"a");
+        translator.translate(b"b", &mut output);
+        assert_eq!(&output[..], b"a\rb");
+
+        // or until flushed
+        translator.translate(b"\r", &mut output);
+        assert!(translator.pending());
+        translator.flush(&mut output);
+        assert!(!translator.pending());
+        translator.translate(b"\n", &mut output);
+        assert_eq!(&output[..], b"a\rb\r\n");
+
+        // A CR received with the old settings is forgotten
+        translator.translate(b"\r", &mut output);
+        translator.reset();
+        translator.translate(b"c", &mut output);
+        assert_eq!(&output[..], b"a\rb\r\nc");
+
+        let mut translator = InputTranslator::new(InputMap::Cr);
+        translator.translate(b"\r", &mut output);
+        assert!(!translator.pending());
+    }
+}
@@ -2,7 +2,9 @@
 
 use bytes::{Buf, BufMut};
 
-/// Mixin [get_chars](#tymethod.get_chars) into types implements [Buf](../../bytes/trait.Buf.html) and
+use crate::encoding::{Decoded, Encoding};
+
+/// Mixin [get_chars](#method.get_chars) into types implements [Buf](../../bytes/trait.Buf.html) and
 /// [BufMut](../../bytes/trait.BufMut.html).
 pub trait GetCharsMixin<B> {
     /// Iterate byte stream as UTF-8 string.
@@ -30,34 +32,70 @@ pub trait GetCharsMixin<B> {
     ///
     ///     assert_eq!(buffer.get_u8(), 0xc7);
     /// ```
-    fn get_chars(&mut self) -> GetCharsIter<B>;
+    fn get_chars(&mut self) -> GetCharsIter<'_, B> {
+        self.get_chars_in(Encoding::Utf8)
+    }
+
+    /// Iterate byte stream as string in the encoding.
+    ///
+    /// As [get_chars](#method.get_chars), imcomplete character sequence will be left in the
+    /// buffer.
+    ///
+    /// ```
+    ///     # use serialcat::{encoding::Encoding, util::{GetChars, GetCharsMixin as _}};
+    ///     # use bytes::{Buf as _, BytesMut};
+    ///
+    ///     let mut buffer = BytesMut::from(&b"\x88\x9f\xa0\x82"[..]); // Shift_JIS
+    ///
+    ///     let mut iter = buffer.get_chars_in(Encoding::ShiftJis);
+    ///     assert_eq!(iter.next(), Some(GetChars::Char('亜')));
+    ///     assert_eq!(iter.bytes(), b"\x88\x9f");
+    ///     assert_eq!(iter.next(), Some(GetChars::Err(0xa0)));
+    ///     assert_eq!(iter.next(), None); // 0x82 was kept in the buffer
+    ///
+    ///     assert_eq!(buffer.get_u8(), 0x82);
+    /// ```
+    fn get_chars_in(&mut self, encoding: Encoding) -> GetCharsIter<'_, B>;
 }
 
 impl<B> GetCharsMixin<B> for B
 where
     B: Buf + BufMut,
 {
-    fn get_chars(&mut self) -> GetCharsIter<B> {
+    fn get_chars_in(&mut self, encoding: Encoding) -> GetCharsIter<'_, B> {
         GetCharsIter {
             inner: self,
+            encoding,
             processing: [0; 4],
             processing_len: 0,
+            last: [0; 4],
+            last_len: 0,
         }
     }
 }
 
 /// Interator to read chars.
 ///
-/// see [GetCharsMixin::get_chars](trait.GetCharsMixin.html#tymethod.get_chars).
+/// see [GetCharsMixin::get_chars](trait.GetCharsMixin.html#method.get_chars).
 pub struct GetCharsIter<'a, B> {
     inner: &'a mut B,
+    encoding: Encoding,
     processing: [u8; 4],
     processing_len: usize,
+    last: [u8; 4],
+    last_len: usize,
+}
+
+impl<'a, B> GetCharsIter<'a, B> {
+    /// Bytes of the character returned last.
+    pub fn bytes(&self) -> &[u8] {
+        &self.last[..self.last_len]
+    }
 }
 
-/// Result of [GetCharsMixin::get_chars](trait.GetCharsMixin.html#tymethod.get_chars).
+/// Result of [GetCharsMixin::get_chars](trait.GetCharsMixin.html#method.get_chars).
 ///
-/// see [GetCharsMixin::get_chars](trait.GetCharsMixin.html#tymethod.get_chars).
+/// see [GetCharsMixin::get_chars](trait.GetCharsMixin.html#method.get_chars).
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum GetChars {
     /// A valid character.
@@ -83,39 +121,23 @@ where
             return None;
         }
 
-        let (str, err) = match std::str::from_utf8(buf) {
-            Ok(str) => (str, None), // None will not be used
-            Err(e) => {
-                let str = unsafe { std::str::from_utf8_unchecked(&buf[..e.valid_up_to()]) };
-                (str, e.error_len())
+        let (ch, len) = match self.encoding.decode(buf) {
+            Decoded::Char(c, len) => (GetChars::Char(c), len),
+            Decoded::Invalid => (GetChars::Err(buf[0]), 1),
+            Decoded::Incomplete => {
+                self.inner.put_slice(buf);
+                self.processing_len = 0;
+
+                return None;
             }
         };
 
-        if !str.is_empty() {
-            let len = (1..)
-                .find_map(|l| {
-                    if str.is_char_boundary(l) {
-                        Some(l)
-                    } else {
-                        None
-                    }
-                })
-                .unwrap();
-            self.processing[..buf.len() - len].copy_from_slice(&buf[len..]);
-            self.processing_len = buf.len() - len;
-
-            let c = str.chars().next().unwrap();
-            Some(GetChars::Char(c))
-        } else if err.is_some() {
-            self.processing[..buf.len() - 1].copy_from_slice(&buf[1..]);
-            self.processing_len = buf.len() - 1;
-
-            Some(GetChars::Err(buf[0]))
-        } else {
-            self.inner.put_slice(buf);
-
-            None
-        }
+        self.last[..len].copy_from_slice(&buf[..len]);
+        self.last_len = len;
+        self.processing[..buf.len() - len].copy_from_slice(&buf[len..]);
+        self.processing_len = buf.len() - len;
+
+        Some(ch)
     }
 }
 
@@ -223,4 +245,27 @@ mod tests {
         assert_eq!(iter.next(), None);
         assert!(!buffer.has_remaining());
     }
+
+    #[test]
+    fn get_chars_in_encoding() {
+        // EUC-JP `亜` and JIS X 0212 `丂` split between buffers
+        let mut buffer = BytesMut::new();
+        buffer.put_slice(&b"a\xb0\xa1\x8f\xb0"[..]);
+        let mut iter = buffer.get_chars_in(Encoding::EucJp);
+        assert_eq!(iter.next(), Some(GetChars::Char('a')));
+        assert_eq!(iter.bytes(), b"a");
+        assert_eq!(iter.next(), Some(GetChars::Char('亜')));
+        assert_eq!(iter.bytes(), b"\xb0\xa1");
+        assert_eq!(iter.next(), None);
+        assert_eq!(&buffer[..], b"\x8f\xb0");
+
+        buffer.put_slice(&b"\xa1\xff"[..]);
+        let mut iter = buffer.get_chars_in(Encoding::EucJp);
+        assert_eq!(iter.next(), Some(GetChars::Char('丂')));
+        assert_eq!(iter.bytes(), b"\x8f\xb0\xa1");
+        assert_eq!(iter.next(), Some(GetChars::Err(0xff)));
+        assert_eq!(iter.bytes(), b"\xff");
+        assert_eq!(iter.next(), None);
+        assert!(!buffer.has_remaining());
+    }
 }
@@ -0,0 +1,223 @@
+//! Patterns of received lines to end the session
+
+use crate::{
+    encoding::Encoding,
+    translate::{InputMap, InputTranslator},
+    util::{GetChars, GetCharsMixin as _},
+};
+use anyhow::{bail, Context as _, Error, Result};
+use bytes::BytesMut;
+use regex::Regex;
+use std::str::FromStr;
+
+/// Characters of a line to match. The rest is ignored.
+const LINE_LIMIT: usize = 4096;
+
+/// Pattern with the exit status when it matches.
+///
+/// Parsed from `[STATUS:]REGEX`, where the status is 0 by default.
+///
+/// ```
+///     # use serialcat::watch::ExitPattern;
+///
+///     let pattern: ExitPattern = "3:ERROR \\d+".parse().unwrap();
+///     assert_eq!(pattern.status, 3);
+///     assert_eq!(pattern.regex.as_str(), "ERROR \\d+");
+///     assert_eq!("PASS".parse::<ExitPattern>().unwrap().status, 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExitPattern {
+    pub status: i32,
+    pub regex: Regex,
+}
+
+impl ExitPattern {
+    /// Pattern with the status.
+    pub fn new(status: i32, regex: &str) -> Result<ExitPattern> {
+        let regex = Regex::new(regex).with_context(|| format!("Invalid pattern: {}", regex))?;
+        Ok(ExitPattern { status, regex })
+    }
+}
+
+impl FromStr for ExitPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExitPattern> {
+        let (status, regex) = match s.find(':') {
+            Some(i) if i > 0 && s[..i].bytes().all(|b| b.is_ascii_digit()) => {
+                match s[..i].parse() {
+                    Ok(status) if status <= 255 => (status, &s[i + 1..]),
+                    _ => bail!("Invalid exit status: {}", &s[..i]),
+                }
+            }
+            _ => (0, s),
+        };
+        ExitPattern::new(status, regex)
+    }
+}
+
+impl PartialEq for ExitPattern {
+    fn eq(&self, other: &ExitPattern) -> bool {
+        self.status == other.status && self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+impl Eq for ExitPattern {}
+
+/// Matcher of patterns against received lines.
+///
+/// Line endings are translated as displayed, and bytes are decoded in the encoding. Lines end with
+/// LF, and a CR before LF is removed.
+///
+/// ```
+///     # use serialcat::{encoding::Encoding, translate::InputMap, watch::{ExitPattern, LineWatcher}};
+///
+///     let patterns = vec![ExitPattern::new(1, "FAIL").unwrap(), ExitPattern::new(0, "PASS").unwrap()];
+///     let mut watcher = LineWatcher::new(patterns, Encoding::Utf8, InputMap::None);
+///     assert_eq!(watcher.feed(b"test 1: PA"), None);
+///     assert_eq!(watcher.feed(b"SS\r\n"), Some(0));
+/// ```
+#[derive(Debug)]
+pub struct LineWatcher {
+    patterns: Vec<ExitPattern>,
+    encoding: Encoding,
+    translator: InputTranslator,
+    pending: BytesMut,
+    line: String,
+    line_len: usize,
+}
+
+impl LineWatcher {
+    /// Watcher of the patterns. The former pattern wins when a line matches some of them.
+    pub fn new(patterns: Vec<ExitPattern>, encoding: Encoding, imap: InputMap) -> LineWatcher {
+        LineWatcher {
+            patterns,
+            encoding,
+            translator: InputTranslator::new(imap),
+            pending: BytesMut::new(),
+            line: String::new(),
+            line_len: 0,
+        }
+    }
+
+    /// Whether no pattern is given.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Feed received bytes, and return the exit status of the first line matching a pattern.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<i32> {
+        self.translator.translate(bytes, &mut self.pending);
+        let mut pending = std::mem::take(&mut self.pending);
+        let mut status = None;
+        for c in pending.get_chars_in(self.encoding) {
+            let c = match c {
+                GetChars::Char('\n') => {
+                    status = self.end_line();
+                    if status.is_some() {
+                        break;
+                    }
+                    continue;
+                }
+                GetChars::Char(c) => c,
+                GetChars::Err(_) => char::REPLACEMENT_CHARACTER,
+            };
+            if self.line_len < LINE_LIMIT {
+                self.line.push(c);
+                self.line_len += 1;
+            }
+        }
+        self.pending = pending;
+        status
+    }
+
+    fn end_line(&mut self) -> Option<i32> {
+        let line = self.line.strip_suffix('\r').unwrap_or(&self.line);
+        let status = self
+            .patterns
+            .iter()
+            .find(|pattern| pattern.regex.is_match(line))
+            .map(|pattern| pattern.status);
+        self.line.clear();
+        self.line_len = 0;
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pattern() {
+        for (arg, status, regex) in &[
+            ("PASS", 0, "PASS"),
+            ("0:PASS", 0, "PASS"),
+            ("42:^ERROR: .*", 42, "^ERROR: .*"),
+            ("time: \\d+", 0, "time: \\d+"),
+            (":x", 0, ":x"),
+            ("3:", 3, ""),
+        ] {
+            let pattern: ExitPattern = arg.parse().unwrap();
+            assert_eq!(pattern.status, *status);
+            assert_eq!(pattern.regex.as_str(), *regex);
+        }
+        "256:x".parse::<ExitPattern>().unwrap_err();
+        "(".parse::<ExitPattern>().unwrap_err();
+    }
+
+    #[test]
+    fn watch_lines() {
+        let patterns = vec![
+            ExitPattern::new(1, "^FAIL").unwrap(),
+            ExitPattern::new(2, "ERROR$").unwrap(),
+            ExitPattern::new(0, "PASS").unwrap(),
+        ];
+        let mut watcher = LineWatcher::new(patterns.clone(), Encoding::Utf8, InputMap::None);
+        assert_eq!(watcher.feed(b"booting\r\nno FAIL\r\n"), None);
+        // Lines are matched when they end
+        assert_eq!(watcher.feed(b"PASS"), None);
+        assert_eq!(watcher.feed(b"\n"), Some(0));
+        // CR before LF is not a part of the line
+        assert_eq!(watcher.feed(b"ERROR\r\n"), Some(2));
+        // The former pattern wins
+        assert_eq!(watcher.feed(b"FAIL PASS\n"), Some(1));
+
+        // Decoded in the encoding
+        let mut watcher = LineWatcher::new(
+            vec![ExitPattern::new(5, "^合格$").unwrap()],
+            Encoding::ShiftJis,
+            InputMap::None,
+        );
+        assert_eq!(watcher.feed(b"\x8d\x87"), None);
+        assert_eq!(watcher.feed(b"\x8a"), None);
+        assert_eq!(watcher.feed(b"\x69\n"), Some(5));
+
+        // Invalid bytes are replaced
+        let mut watcher = LineWatcher::new(
+            vec![ExitPattern::new(0, "^a\u{fffd}b$").unwrap()],
+            Encoding::Utf8,
+            InputMap::None,
+        );
+        assert_eq!(watcher.feed(b"a\xffb\n"), Some(0));
+
+        // Long lines are truncated
+        let mut watcher = LineWatcher::new(patterns, Encoding::Utf8, InputMap::None);
+        let mut line = vec![b'.'; LINE_LIMIT];
+        line.extend_from_slice(b"ERROR\n");
+        assert_eq!(watcher.feed(&line), None);
+    }
+
+    #[test]
+    fn watch_mapped_lines() {
+        let patterns = vec![ExitPattern::new(0, "^PASS$").unwrap()];
+        // CR-only lines end only when CR is mapped
+        let mut watcher = LineWatcher::new(patterns.clone(), Encoding::Utf8, InputMap::None);
+        assert_eq!(watcher.feed(b"PASS\r"), None);
+        let mut watcher = LineWatcher::new(patterns.clone(), Encoding::Utf8, InputMap::Cr);
+        assert_eq!(watcher.feed(b"PASS\r"), Some(0));
+        // LFCR is one line ending
+        let mut watcher = LineWatcher::new(patterns, Encoding::Utf8, InputMap::LfCr);
+        assert_eq!(watcher.feed(b"\n\rPASS\n\r"), Some(0));
+    }
+}